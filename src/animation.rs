@@ -5,16 +5,68 @@ use std::error::Error as StdError;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 
-use crate::config;
+use crate::config::{Config, StereoLayout};
 use crate::signal_processing::SignalProcessing;
 
 type Result<T> = std::result::Result<T, AnimationError>;
 
+/// Values for tunable animation parameters by name, see `Animation::set_parameter()`.
+pub type Parameters = BTreeMap<String, f32>;
+
 pub mod particles;
 pub mod sparkles;
 pub mod racers;
 pub mod spectrum;
+pub mod layers;
+pub mod switcher;
+
+/// Frame rate for which the per-frame constants of the animations (decay factors, filter
+/// coefficients) are tuned: 48000 Hz with 256 samples per update.
+pub const REFERENCE_FPS: f32 = 187.5;
+
+/// Convert a per-frame decay factor tuned for `REFERENCE_FPS` to the given frame rate, so the
+/// decay takes the same time at any sample rate.
+pub fn decay_per_frame(factor: f32, fps: f32) -> f32
+{
+	factor.powf(REFERENCE_FPS / fps)
+}
+
+/// Convert the coefficient of a first-order lowpass filter tuned for `REFERENCE_FPS` to the given
+/// frame rate.
+pub fn alpha_per_frame(alpha: f32, fps: f32) -> f32
+{
+	1.0 - decay_per_frame(1.0 - alpha, fps)
+}
+
+/// All available animations with a short description, as selectable in the configuration and on
+/// the command line.
+pub const AVAILABLE: [(&str, &str); 5] = [
+	("particles", "Randomly distributed particles of light, one color per frequency band"),
+	("sparkles",  "Sparks moving along the strips, plus bass particles"),
+	("racers",    "Points of light racing along the strips with audio-dependent speed"),
+	("spectrum",  "Spectrum display (experimental)"),
+	("layers",    "Composite of the animations configured in [[layers]]"),
+];
+
+pub fn names() -> Vec<&'static str>
+{
+	AVAILABLE.iter().map(|(name, _)| *name).collect()
+}
+
+/// Construct the animation with the given name (see `AVAILABLE`).
+pub fn create(name: &str, sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Result<Box<dyn Animation>>
+{
+	match name {
+		"particles" => Ok(Box::new(particles::Particles::new(sigproc, config))),
+		"sparkles"  => Ok(Box::new(sparkles::Sparkles::new(sigproc, config))),
+		"racers"    => Ok(Box::new(racers::Racers::new(sigproc, config))),
+		"spectrum"  => Ok(Box::new(spectrum::Spectrum::new(sigproc, config))),
+		"layers"    => Ok(Box::new(layers::Layers::new(sigproc, config))),
+		_           => Err(AnimationError::ErrorMessage(format!("Unknown animation: {}", name))),
+	}
+}
 
 /////////// Error Type and Implementation ////////////
 
@@ -79,6 +131,15 @@ impl Color
 		self.w += other.w;
 	}
 
+	/// Linear interpolation between this color (factor = 0.0) and `other` (factor = 1.0).
+	pub fn mix(&self, other: &Color, factor: f32) -> Color
+	{
+		let mut c = self.scaled_copy(1.0 - factor);
+
+		c.add(&other.scaled_copy(factor));
+		c
+	}
+
 	fn _limit_component(c: &mut f32)
 	{
 		if *c > 1.0 {
@@ -119,13 +180,104 @@ impl Color
 	}
 }
 
+/// A heap-allocated frame of colors for all LEDs, organized in strips of equal length.
+#[derive(Clone)]
+pub struct FrameBuffer
+{
+	num_strips:         usize,
+	num_leds_per_strip: usize,
+
+	colors: Vec<Color>,
+}
+
+impl FrameBuffer
+{
+	/// Create a frame buffer with all LEDs set to black.
+	pub fn new(num_strips: usize, num_leds_per_strip: usize) -> FrameBuffer
+	{
+		FrameBuffer {
+			num_strips,
+			num_leds_per_strip,
+			colors: vec![Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; num_strips * num_leds_per_strip],
+		}
+	}
+
+	pub fn num_strips(&self) -> usize
+	{
+		self.num_strips
+	}
+
+	pub fn num_leds_per_strip(&self) -> usize
+	{
+		self.num_leds_per_strip
+	}
+
+	pub fn num_leds_total(&self) -> usize
+	{
+		self.colors.len()
+	}
+
+	pub fn strip_mut(&mut self, strip: usize) -> &mut [Color]
+	{
+		let start = strip * self.num_leds_per_strip;
+		&mut self.colors[start .. start + self.num_leds_per_strip]
+	}
+
+	pub fn led_mut(&mut self, strip: usize, led: usize) -> &mut Color
+	{
+		&mut self.strip_mut(strip)[led]
+	}
+
+	/// Iterate over all LEDs, strip by strip.
+	pub fn iter(&self) -> std::slice::Iter<'_, Color>
+	{
+		self.colors.iter()
+	}
+
+	pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Color>
+	{
+		self.colors.iter_mut()
+	}
+
+	pub fn fill(&mut self, color: Color)
+	{
+		self.colors.iter_mut().for_each(|c| *c = color);
+	}
+
+	/// Channel (0 = left, 1 = right) shown by an LED in the given stereo layout.
+	pub fn stereo_channel(&self, layout: StereoLayout, strip: usize, led: usize) -> usize
+	{
+		match layout {
+			StereoLayout::Strips if self.num_strips > 1 => (2 * strip >= self.num_strips) as usize,
+			_ => (2 * led >= self.num_leds_per_strip) as usize,
+		}
+	}
+}
+
+/// Weight of a channel (0 = left, 1 = right) for the given stereo balance. The weights of both
+/// channels add up to 2, so a centered signal has the weight 1 on both sides.
+pub fn stereo_weight(balance: f32, channel: usize) -> f32
+{
+	match channel {
+		0 => 1.0 - balance,
+		_ => 1.0 + balance,
+	}
+}
+
 /////////// Animation Trait ////////////
 
 pub trait Animation {
-	fn new(sigproc: Rc<RefCell<SignalProcessing>>) -> Self;
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Self where Self: Sized;
 
 	fn init(&mut self) -> Result<()>;
 	fn periodic(&mut self) -> Result<()>;
 
-	fn get_colorlist(&self) -> &[ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS];
+	/// Change a tunable parameter of the animation. The default implementation rejects all
+	/// parameters.
+	fn set_parameter(&mut self, name: &str, _value: f32) -> Result<()>
+	{
+		Err(AnimationError::ErrorMessage(format!("Unknown parameter: {}", name)))
+	}
+
+	fn get_colorlist(&self) -> &FrameBuffer;
 }
@@ -0,0 +1,135 @@
+// vim: noet
+
+use crate::animation::{self, Color, FrameBuffer, Animation, Result};
+use crate::signal_processing::SignalProcessing;
+use crate::config::{BlendMode, Config, LayerConfig};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/*
+ * A layer is an animation that is composited onto the layers below it.
+ */
+struct Layer
+{
+	animation: Box<dyn Animation>,
+	config:    LayerConfig,
+}
+
+impl Layer
+{
+	fn covers(&self, strip: usize, led_total: usize) -> bool
+	{
+		let in_leds = self.config.leds.is_none_or(|(start, end)| (start..end).contains(&led_total));
+		let in_strips = self.config.strips.is_none_or(|(start, end)| (start..end).contains(&strip));
+
+		in_leds && in_strips
+	}
+}
+
+fn blend_component(mode: BlendMode, base: f32, layer: f32) -> f32
+{
+	match mode {
+		BlendMode::Add       => base + layer,
+		BlendMode::Max       => base.max(layer),
+		BlendMode::Multiply  => base * layer,
+		BlendMode::Screen    => 1.0 - (1.0 - base) * (1.0 - layer),
+		BlendMode::AlphaOver => layer,
+	}
+}
+
+/// Blend a layer color onto a base color. For alpha-over, the brightest component of the layer
+/// color is used as its alpha, so black areas of a layer are transparent.
+fn blend(mode: BlendMode, opacity: f32, base: &Color, layer: &Color) -> Color
+{
+	let mut blended = *base;
+
+	for i in 0..4 {
+		let b = blended.ref_by_index_mut(i).unwrap();
+		let l = layer.ref_by_index(i).unwrap();
+
+		*b = blend_component(mode, *b, *l);
+	}
+
+	let factor = match mode {
+		BlendMode::AlphaOver => opacity * layer.r.max(layer.g).max(layer.b).max(layer.w).min(1.0),
+		_                    => opacity,
+	};
+
+	let mut result = base.mix(&blended, factor);
+	result.limit();
+	result
+}
+
+pub struct Layers
+{
+	layers: Vec<Layer>,
+
+	colorlists: FrameBuffer,
+
+	config: Config,
+
+	sigproc: Rc<RefCell<SignalProcessing>>,
+}
+
+impl Animation for Layers
+{
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Layers
+	{
+		Layers {
+			layers: Vec::with_capacity(config.layers.len()),
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			config: config.clone(),
+			sigproc,
+		}
+	}
+
+	fn init(&mut self) -> Result<()>
+	{
+		for layer_config in self.config.layers.iter() {
+			let mut anim = animation::create(&layer_config.animation, self.sigproc.clone(), &self.config)?;
+
+			anim.init()?;
+
+			for (param, value) in layer_config.params.iter() {
+				anim.set_parameter(param, *value)?;
+			}
+
+			self.layers.push(Layer {
+				animation: anim,
+				config: layer_config.clone(),
+			});
+		}
+
+		Ok(())
+	}
+
+	fn periodic(&mut self) -> Result<()>
+	{
+		for layer in self.layers.iter_mut() {
+			layer.animation.periodic()?;
+		}
+
+		// composite all layers from bottom to top onto black
+		self.colorlists.fill(Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0});
+
+		let num_leds_per_strip = self.colorlists.num_leds_per_strip();
+
+		for layer in self.layers.iter() {
+			let layer_colors = layer.animation.get_colorlist();
+
+			for (i, (out, color)) in self.colorlists.iter_mut().zip(layer_colors.iter()).enumerate() {
+				if layer.covers(i / num_leds_per_strip, i) {
+					*out = blend(layer.config.blend, layer.config.opacity, out, color);
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn get_colorlist(&self) -> &FrameBuffer
+	{
+		&self.colorlists
+	}
+}
@@ -1,15 +1,16 @@
 // vim: noet
 
-use crate::animation::{Color, Animation, Result};
+use crate::animation::{self, Color, FrameBuffer, Animation, AnimationError, Result};
 use crate::signal_processing::SignalProcessing;
-use crate::config;
+use crate::signal_processing::agc::{self, Agc};
+use crate::signal_processing::onset::OnsetBand;
+use crate::config::{Config, StereoLayout};
 
 use std::rc::Rc;
 use std::cell::RefCell;
 
 use rand::Rng;
 
-const COOLDOWN_FACTOR     : f32 = 0.99980;
 const RGB_EXPONENT        : f32 = 1.8;
 const W_EXPONENT          : f32 = 2.2;
 const FADE_FACTOR         : f32 = 0.98;
@@ -17,24 +18,43 @@ const AVG_LEDS_ACTIVATED  : f32 = 0.02;
 const WHITE_EXTRA_SCALE   : f32 = 0.5;
 const CONDENSATION_FACTOR : f32 = 5.0;
 
+// additional energy for a color if an onset was detected in its band
+const ONSET_BOOST         : f32 = 1.0;
+
 pub struct Particles
 {
-	energy       : [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
-	max_energy   : Color,
+	energy       : FrameBuffer,
+
+	// normalization of the band levels, one per color
+	agc          : Vec<Agc>,
+
+	colorlists   : FrameBuffer,
 
-	colorlists   : [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+	stereo_layout : StereoLayout,
+
+	fps_animation      : f32,
+
+	// tunable parameters, see set_parameter()
+	fade_factor        : f32,
+	avg_leds_activated : f32,
+	white_scale        : f32,
 
 	sigproc: Rc<RefCell<SignalProcessing>>,
 }
 
 impl Animation for Particles
 {
-	fn new(sigproc: Rc<RefCell<SignalProcessing>>) -> Particles
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Particles
 	{
 		Particles {
-			energy:     [ [Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
-			max_energy: Color{r: 1.0, g: 1.0, b: 1.0, w: 1.0},
-			colorlists: [ [Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+			energy:     FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			agc:        (0..4).map(|_| Agc::new(&config.agc, 1.0 / config.fps_animation())).collect(),
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			stereo_layout: config.stereo_layout,
+			fps_animation: config.fps_animation(),
+			fade_factor: animation::decay_per_frame(FADE_FACTOR, config.fps_animation()),
+			avg_leds_activated: AVG_LEDS_ACTIVATED,
+			white_scale: WHITE_EXTRA_SCALE,
 			sigproc: sigproc,
 		}
 	}
@@ -48,63 +68,62 @@ impl Animation for Particles
 	{
 		let sigproc = self.sigproc.borrow();
 
-		// extract frequency band energies
-		let cur_energy = Color{
-			r: sigproc.get_energy_in_band(    0.0,   400.0),
-			g: sigproc.get_energy_in_band(  400.0,  4000.0),
-			b: sigproc.get_energy_in_band( 4000.0, 12000.0),
-			w: sigproc.get_energy_in_band(12000.0, 22000.0)};
-
-		// track the maximum energy with cooldown
-		self.max_energy.r *= COOLDOWN_FACTOR;
-		if cur_energy.r > self.max_energy.r {
-			self.max_energy.r = cur_energy.r;
-		}
+		// extract the frequency band levels and normalize them
+		let mut cur_energy = Color{
+			r: sigproc.get_level_in_band(    0.0,   400.0),
+			g: sigproc.get_level_in_band(  400.0,  4000.0),
+			b: sigproc.get_level_in_band( 4000.0, 12000.0),
+			w: sigproc.get_level_in_band(12000.0, 22000.0)};
 
-		self.max_energy.g *= COOLDOWN_FACTOR;
-		if cur_energy.g > self.max_energy.g {
-			self.max_energy.g = cur_energy.g;
+		for (coloridx, agc) in self.agc.iter_mut().enumerate() {
+			let energy = cur_energy.ref_by_index_mut(coloridx).unwrap();
+			*energy = agc.update(*energy);
 		}
 
-		self.max_energy.b *= COOLDOWN_FACTOR;
-		if cur_energy.b > self.max_energy.b {
-			self.max_energy.b = cur_energy.b;
-		}
-
-		self.max_energy.w *= COOLDOWN_FACTOR;
-		if cur_energy.w > self.max_energy.w {
-			self.max_energy.w = cur_energy.w;
-		}
+		// with separate channels, particles are more likely on the side of the louder channel
+		let balance = Color{
+			r: sigproc.get_balance_in_band(    0.0,   400.0),
+			g: sigproc.get_balance_in_band(  400.0,  4000.0),
+			b: sigproc.get_balance_in_band( 4000.0, 12000.0),
+			w: sigproc.get_balance_in_band(12000.0, 22000.0)};
 
 		// fade all LEDs towards black
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				self.energy[strip][led].scale(FADE_FACTOR);
-			}
+		for led in self.energy.iter_mut() {
+			led.scale(self.fade_factor);
 		}
 
 		// distribute the energy for each color
 		let new_energy = Color{
-			r: (cur_energy.r / self.max_energy.r).powf(RGB_EXPONENT),
-			g: (cur_energy.g / self.max_energy.g).powf(RGB_EXPONENT),
-			b: (cur_energy.b / self.max_energy.b).powf(RGB_EXPONENT),
-			w: (cur_energy.w / self.max_energy.w).powf(W_EXPONENT),
+			r: cur_energy.r.powf(RGB_EXPONENT),
+			g: cur_energy.g.powf(RGB_EXPONENT),
+			b: cur_energy.b.powf(RGB_EXPONENT),
+			w: cur_energy.w.powf(W_EXPONENT),
 		};
 
+		// spawn more particles on kick (red), snare (green) and hi-hat (blue) onsets
+		let mut new_energy = new_energy;
+
+		for (band, coloridx) in [(OnsetBand::Kick, 0), (OnsetBand::Snare, 1), (OnsetBand::HiHat, 2)] {
+			if sigproc.is_onset(band) {
+				*new_energy.ref_by_index_mut(coloridx).unwrap() *= 1.0 + ONSET_BOOST;
+			}
+		}
+
 		let mut remaining_energy = new_energy;
-		remaining_energy.scale(AVG_LEDS_ACTIVATED * config::NUM_LEDS_TOTAL as f32);
+		remaining_energy.scale(self.avg_leds_activated * self.energy.num_leds_total() as f32);
 
 		let mut rng = rand::thread_rng();
 
 		for coloridx in 0..=3 {
 			let new_energy_ref = new_energy.ref_by_index(coloridx).unwrap();
 			let rem_energy_ref = remaining_energy.ref_by_index_mut(coloridx).unwrap();
+			let balance_ref = balance.ref_by_index(coloridx).unwrap();
 
 			while *rem_energy_ref > 0.0 {
 				let mut rnd_energy = rng.gen::<f32>() * (*new_energy_ref) * CONDENSATION_FACTOR;
 
-				let rnd_strip = rng.gen_range(0..config::NUM_STRIPS);
-				let rnd_led   = rng.gen_range(0..config::NUM_LEDS_PER_STRIP);
+				let rnd_strip = rng.gen_range(0..self.energy.num_strips());
+				let rnd_led   = rng.gen_range(0..self.energy.num_leds_per_strip());
 
 				if rnd_energy > *rem_energy_ref {
 					rnd_energy = *rem_energy_ref;
@@ -113,26 +132,40 @@ impl Animation for Particles
 					*rem_energy_ref -= rnd_energy;
 				}
 
-				let led_ref = self.energy[rnd_strip][rnd_led].ref_by_index_mut(coloridx).unwrap();
-				*led_ref += rnd_energy;
+				let channel = self.energy.stereo_channel(self.stereo_layout, rnd_strip, rnd_led);
+				let weight = animation::stereo_weight(*balance_ref, channel);
+
+				let led_ref = self.energy.led_mut(rnd_strip, rnd_led).ref_by_index_mut(coloridx).unwrap();
+				*led_ref += rnd_energy * weight;
 			}
 		}
 
 		// color post-processing
-		self.colorlists = self.energy;
+		self.colorlists.clone_from(&self.energy);
 
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				self.colorlists[strip][led].w *= WHITE_EXTRA_SCALE;
+		for led in self.colorlists.iter_mut() {
+			led.w *= self.white_scale;
 
-				self.colorlists[strip][led].limit();
-			}
+			led.limit();
+		}
+
+		Ok(())
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f32) -> Result<()>
+	{
+		match name {
+			"fade_factor"        => self.fade_factor = animation::decay_per_frame(value, self.fps_animation),
+			"avg_leds_activated" => self.avg_leds_activated = value,
+			"white_scale"        => self.white_scale = value,
+			_ if agc::PARAMETERS.contains(&name) => self.agc.iter_mut().for_each(|a| a.set_parameter(name, value)),
+			_ => return Err(AnimationError::ErrorMessage(format!("Unknown parameter: {}", name))),
 		}
 
 		Ok(())
 	}
 
-	fn get_colorlist(&self) -> &[ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS]
+	fn get_colorlist(&self) -> &FrameBuffer
 	{
 		return &self.colorlists;
 	}
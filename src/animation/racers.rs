@@ -1,51 +1,64 @@
 // vim: noet
 
-use crate::animation::{Color, Animation, Result};
+use crate::animation::{self, Color, FrameBuffer, Animation, AnimationError, Result};
 use crate::signal_processing::SignalProcessing;
-use crate::config;
+use crate::signal_processing::agc::{self, Agc};
+use crate::signal_processing::silence::MIN_LEVEL;
+use crate::config::{Config, StereoLayout};
 
 use std::rc::Rc;
 use std::cell::RefCell;
 
 use rand::Rng;
 
-const COOLDOWN_FACTOR         : f32 = 0.99980;
 const RGB_EXPONENT            : f32 = 1.5;
 const W_EXPONENT              : f32 = 2.2;
 const W_SCALE                 : f32 = 0.3;
 const ENERGY_FILTER_ALPHA     : f32 = 0.20;
 const BRIGHTNESS_FILTER_ALPHA : f32 = 0.002;
 
-const NUM_RACERS_R        : usize = 10 * config::NUM_LEDS_TOTAL / 300;
-const NUM_RACERS_G        : usize = 10 * config::NUM_LEDS_TOTAL / 300;
-const NUM_RACERS_B        : usize = 10 * config::NUM_LEDS_TOTAL / 300;
+// racers per 300 LEDs
+const NUM_RACERS_R        : usize = 10;
+const NUM_RACERS_G        : usize = 10;
+const NUM_RACERS_B        : usize = 10;
 
-const RACER_MIN_SPEED_R        : f32 =  0.5 / config::FPS_ANIMATION;
-const RACER_MAX_SPEED_R        : f32 = 80.0 / config::FPS_ANIMATION;
+// speeds are given in LEDs per second
+const RACER_MIN_SPEED_R        : f32 =  0.5;
+const RACER_MAX_SPEED_R        : f32 = 80.0;
 const RACER_MIN_BRIGHTNESS_R   : f32 = 0.01;
 const RACER_MAX_BRIGHTNESS_R   : f32 = 1.00;
 
-const RACER_MIN_SPEED_G        : f32 =  0.5 / config::FPS_ANIMATION;
-const RACER_MAX_SPEED_G        : f32 = 80.0 / config::FPS_ANIMATION;
+const RACER_MIN_SPEED_G        : f32 =  0.5;
+const RACER_MAX_SPEED_G        : f32 = 80.0;
 const RACER_MIN_BRIGHTNESS_G   : f32 = 0.01;
 const RACER_MAX_BRIGHTNESS_G   : f32 = 1.00;
 
-const RACER_MIN_SPEED_B        : f32 =  0.5 / config::FPS_ANIMATION;
-const RACER_MAX_SPEED_B        : f32 = 80.0 / config::FPS_ANIMATION;
+const RACER_MIN_SPEED_B        : f32 =  0.5;
+const RACER_MAX_SPEED_B        : f32 = 80.0;
 const RACER_MIN_BRIGHTNESS_B   : f32 = 0.01;
 const RACER_MAX_BRIGHTNESS_B   : f32 = 1.00;
 
 const SPEED_SCALE_RANGE        : f32 = 0.10;
 
+// racers reverse their direction on downbeats and pulse on each beat if the tempo is known at
+// least this well
+const BEAT_MIN_CONFIDENCE      : f32 = 0.3;
+const BEAT_PULSE_SCALE         : f32 = 0.5;
+const BEAT_PULSE_DECAY         : f32 = 8.0;  // per beat
+
+/// Show a level and the normalized range on a scale from MIN_LEVEL to 0 dB.
 fn dbg_bar(min: f32, current: f32, max: f32)
 {
 	const LEN: usize = 60;
 	let mut bar = ['.'; LEN];
 
-	let minpos = ((LEN as f32) * min / max) as usize;
-	let curpos = ((LEN as f32) * current / max) as usize;
+	let pos = |level: f32| ((LEN as f32) * (1.0 - level / MIN_LEVEL)).max(0.0) as usize;
+
+	let minpos = pos(min).min(LEN);
+	let maxpos = pos(max).min(LEN);
+	let curpos = pos(current);
 
-	for idx in minpos..LEN {
+	for idx in minpos..maxpos {
 		bar[idx] = '-';
 	}
 
@@ -74,11 +87,16 @@ struct Racer
 
 	brightness: f32,
 	flare_brightness: f32,
+
+	num_leds_per_strip: usize,
+	num_leds_total: usize,
 }
 
 impl Racer
 {
-	pub fn new(min_speed: f32, max_speed: f32, min_brightness: f32, max_brightness: f32, color: Color, start_pos: f32, direction: i8) -> Racer
+	#[allow(clippy::too_many_arguments)]
+	pub fn new(min_speed: f32, max_speed: f32, min_brightness: f32, max_brightness: f32, color: Color, start_pos: f32, direction: i8,
+	           num_leds_per_strip: usize, num_leds_total: usize) -> Racer
 	{
 		Racer {
 			min_speed: min_speed,
@@ -90,22 +108,38 @@ impl Racer
 			pos: start_pos,
 			brightness: min_brightness,
 			flare_brightness: 0.0,
+			num_leds_per_strip,
+			num_leds_total,
 		}
 	}
 
-	fn _pos2ledstrip(pos: i32) -> (i32, i32)
+	/// Channel (0 = left, 1 = right) shown at the current position of the racer.
+	pub fn stereo_channel(&self, colorlists: &FrameBuffer, layout: StereoLayout) -> usize
+	{
+		let pos = (self.pos as i32).clamp(0, self.num_leds_total as i32 - 1);
+		let (strip, led) = self._pos2ledstrip(pos);
+
+		colorlists.stereo_channel(layout, strip as usize, led as usize)
+	}
+
+	fn _pos2ledstrip(&self, pos: i32) -> (i32, i32)
 	{
-		let strip = pos / (config::NUM_LEDS_PER_STRIP as i32);
+		let strip = pos / (self.num_leds_per_strip as i32);
 
-		let mut led = pos % (config::NUM_LEDS_PER_STRIP as i32);
+		let mut led = pos % (self.num_leds_per_strip as i32);
 
 		if (strip % 2) == 1 {
-			led = (config::NUM_LEDS_PER_STRIP as i32) - led - 1;
+			led = (self.num_leds_per_strip as i32) - led - 1;
 		}
 
 		(strip, led)
 	}
 
+	pub fn reverse(&mut self)
+	{
+		self.direction = -self.direction;
+	}
+
 	pub fn update(&mut self, speed: f32, brightness: f32, flare_brightness: f32)
 	{
 		// move along the strip
@@ -113,7 +147,7 @@ impl Racer
 
 		self.pos += (self.direction as f32) * cur_speed;
 
-		let maxpos = config::NUM_LEDS_TOTAL as f32;
+		let maxpos = self.num_leds_total as f32;
 
 		// if the end is reached, reverse the direction
 		if self.pos >= maxpos {
@@ -128,7 +162,7 @@ impl Racer
 		self.flare_brightness = flare_brightness;
 	}
 
-	pub fn render(&self, colorlists: &mut [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS])
+	pub fn render(&self, colorlists: &mut FrameBuffer)
 	{
 		let brightness = self.min_brightness + self.brightness * (self.max_brightness - self.min_brightness);
 
@@ -143,24 +177,24 @@ impl Racer
 		let led1_color = color.scaled_copy((1.0 - fract_led) * brightness);
 		let led2_color = color.scaled_copy(fract_led * brightness);
 
-		if led1_idx >= 0 && led1_idx < (config::NUM_LEDS_TOTAL as i32) {
-			let (strip, led) = Racer::_pos2ledstrip(led1_idx);
+		if led1_idx >= 0 && led1_idx < (self.num_leds_total as i32) {
+			let (strip, led) = self._pos2ledstrip(led1_idx);
 
-			colorlists[strip as usize][led as usize].add(&led1_color);
+			colorlists.led_mut(strip as usize, led as usize).add(&led1_color);
 		}
 
-		if led2_idx >= 0 && led2_idx < (config::NUM_LEDS_TOTAL as i32) {
-			let (strip, led) = Racer::_pos2ledstrip(led2_idx);
+		if led2_idx >= 0 && led2_idx < (self.num_leds_total as i32) {
+			let (strip, led) = self._pos2ledstrip(led2_idx);
 
-			colorlists[strip as usize][led as usize].add(&led2_color);
+			colorlists.led_mut(strip as usize, led as usize).add(&led2_color);
 		}
 	}
 }
 
 pub struct Racers
 {
-	max_energy          : Color,
-	min_energy          : Color,
+	// normalization of the band levels, one per color
+	agc                 : Vec<Agc>,
 	filtered_energy     : Color,
 	filtered_brightness : Color,
 
@@ -168,7 +202,17 @@ pub struct Racers
 	racers_g : Vec<Racer>,
 	racers_b : Vec<Racer>,
 
-	colorlists : [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+	colorlists : FrameBuffer,
+
+	fps_animation : f32,
+	stereo_layout : StereoLayout,
+
+	// per-frame values derived from the constants above
+	energy_filter_alpha : f32,
+
+	// tunable parameters, see set_parameter()
+	w_scale                 : f32,
+	brightness_filter_alpha : f32,
 
 	frame_count: usize,
 
@@ -177,17 +221,21 @@ pub struct Racers
 
 impl Animation for Racers
 {
-	fn new(sigproc: Rc<RefCell<SignalProcessing>>) -> Racers
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Racers
 	{
 		Racers {
-			max_energy: Color{r: 1.0, g: 1.0, b: 1.0, w: 1.0},
-			min_energy: Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0},
-			filtered_energy: Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0},
+			agc: (0..4).map(|_| Agc::new(&config.agc, 1.0 / config.fps_animation())).collect(),
+			filtered_energy: Color{r: MIN_LEVEL, g: MIN_LEVEL, b: MIN_LEVEL, w: MIN_LEVEL},
 			filtered_brightness: Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0},
-			racers_r: Vec::with_capacity(NUM_RACERS_R),
-			racers_g: Vec::with_capacity(NUM_RACERS_G),
-			racers_b: Vec::with_capacity(NUM_RACERS_B),
-			colorlists: [ [Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+			racers_r: Vec::with_capacity(NUM_RACERS_R * config.num_leds_total() / 300),
+			racers_g: Vec::with_capacity(NUM_RACERS_G * config.num_leds_total() / 300),
+			racers_b: Vec::with_capacity(NUM_RACERS_B * config.num_leds_total() / 300),
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			fps_animation: config.fps_animation(),
+			stereo_layout: config.stereo_layout,
+			energy_filter_alpha: animation::alpha_per_frame(ENERGY_FILTER_ALPHA, config.fps_animation()),
+			w_scale: W_SCALE,
+			brightness_filter_alpha: animation::alpha_per_frame(BRIGHTNESS_FILTER_ALPHA, config.fps_animation()),
 			sigproc: sigproc,
 			frame_count: 0,
 		}
@@ -197,8 +245,12 @@ impl Animation for Racers
 	{
 		let mut rng = rand::thread_rng();
 
-		for _i in 0 .. NUM_RACERS_R {
-			let start_pos = rng.gen::<f32>() * (config::NUM_LEDS_TOTAL as f32);
+		let num_leds_per_strip = self.colorlists.num_leds_per_strip();
+		let num_leds_total = self.colorlists.num_leds_total();
+		let fps = self.fps_animation;
+
+		for _i in 0 .. NUM_RACERS_R * num_leds_total / 300 {
+			let start_pos = rng.gen::<f32>() * (num_leds_total as f32);
 			let speed_scale = 1.0 + SPEED_SCALE_RANGE * (rng.gen::<f32>() - 0.5);
 			let mut dir = rng.gen::<i8>();
 			if dir > 0 {
@@ -208,17 +260,19 @@ impl Animation for Racers
 			}
 
 			self.racers_r.push(Racer::new(
-					RACER_MIN_SPEED_R * speed_scale,
-					RACER_MAX_SPEED_R * speed_scale,
+					RACER_MIN_SPEED_R / fps * speed_scale,
+					RACER_MAX_SPEED_R / fps * speed_scale,
 					RACER_MIN_BRIGHTNESS_R,
 					RACER_MAX_BRIGHTNESS_R,
 					Color{r: 1.0, g: 0.0, b: 0.0, w: 0.0},
 					start_pos,
-					dir));
+					dir,
+					num_leds_per_strip,
+					num_leds_total));
 		}
 
-		for _i in 0 .. NUM_RACERS_G {
-			let start_pos = rng.gen::<f32>() * (config::NUM_LEDS_TOTAL as f32);
+		for _i in 0 .. NUM_RACERS_G * num_leds_total / 300 {
+			let start_pos = rng.gen::<f32>() * (num_leds_total as f32);
 			let speed_scale = 1.0 + SPEED_SCALE_RANGE * (rng.gen::<f32>() - 0.5);
 			let mut dir = rng.gen::<i8>();
 			if dir > 0 {
@@ -228,17 +282,19 @@ impl Animation for Racers
 			}
 
 			self.racers_g.push(Racer::new(
-					RACER_MIN_SPEED_G * speed_scale,
-					RACER_MAX_SPEED_G * speed_scale,
+					RACER_MIN_SPEED_G / fps * speed_scale,
+					RACER_MAX_SPEED_G / fps * speed_scale,
 					RACER_MIN_BRIGHTNESS_G,
 					RACER_MAX_BRIGHTNESS_G,
 					Color{r: 0.0, g: 1.0, b: 0.0, w: 0.0},
 					start_pos,
-					dir));
+					dir,
+					num_leds_per_strip,
+					num_leds_total));
 		}
 
-		for _i in 0 .. NUM_RACERS_B {
-			let start_pos = rng.gen::<f32>() * (config::NUM_LEDS_TOTAL as f32);
+		for _i in 0 .. NUM_RACERS_B * num_leds_total / 300 {
+			let start_pos = rng.gen::<f32>() * (num_leds_total as f32);
 			let speed_scale = 1.0 + SPEED_SCALE_RANGE * (rng.gen::<f32>() - 0.5);
 			let mut dir = rng.gen::<i8>();
 			if dir > 0 {
@@ -248,13 +304,15 @@ impl Animation for Racers
 			}
 
 			self.racers_b.push(Racer::new(
-					RACER_MIN_SPEED_B * speed_scale,
-					RACER_MAX_SPEED_B * speed_scale,
+					RACER_MIN_SPEED_B / fps * speed_scale,
+					RACER_MAX_SPEED_B / fps * speed_scale,
 					RACER_MIN_BRIGHTNESS_B,
 					RACER_MAX_BRIGHTNESS_B,
 					Color{r: 0.0, g: 0.0, b: 1.0, w: 0.0},
 					start_pos,
-					dir));
+					dir,
+					num_leds_per_strip,
+					num_leds_total));
 		}
 
 		Ok(())
@@ -264,76 +322,45 @@ impl Animation for Racers
 	{
 		let sigproc = self.sigproc.borrow();
 
-		// extract frequency band energies
+		// extract frequency band levels
 		let cur_energy = Color{
-			r: sigproc.get_energy_in_band(    0.0,   400.0),
-			g: sigproc.get_energy_in_band(  400.0,  4000.0),
-			b: sigproc.get_energy_in_band( 4000.0, 12000.0),
-			w: sigproc.get_energy_in_band(12000.0, 22000.0)};
+			r: sigproc.get_level_in_band(    0.0,   400.0),
+			g: sigproc.get_level_in_band(  400.0,  4000.0),
+			b: sigproc.get_level_in_band( 4000.0, 12000.0),
+			w: sigproc.get_level_in_band(12000.0, 22000.0)};
+
+		// with separate channels, racers are faster and brighter on the side of the louder channel
+		let balance = Color{
+			r: sigproc.get_balance_in_band(    0.0,   400.0),
+			g: sigproc.get_balance_in_band(  400.0,  4000.0),
+			b: sigproc.get_balance_in_band( 4000.0, 12000.0),
+			w: sigproc.get_balance_in_band(12000.0, 22000.0)};
 
 		for i in 0..4 {
 			let f = self.filtered_energy.ref_by_index_mut(i).unwrap();
 			let n = cur_energy.ref_by_index(i).unwrap();
 
-			*f = (1.0 - ENERGY_FILTER_ALPHA) * (*f) + ENERGY_FILTER_ALPHA * (*n);
-		}
-
-		// track the maximum energy with cooldown
-		self.max_energy.r *= COOLDOWN_FACTOR;
-		if self.filtered_energy.r > self.max_energy.r {
-			self.max_energy.r = self.filtered_energy.r;
-		}
-
-		self.max_energy.g *= COOLDOWN_FACTOR;
-		if self.filtered_energy.g > self.max_energy.g {
-			self.max_energy.g = self.filtered_energy.g;
+			*f = (1.0 - self.energy_filter_alpha) * (*f) + self.energy_filter_alpha * (*n);
 		}
 
-		self.max_energy.b *= COOLDOWN_FACTOR;
-		if self.filtered_energy.b > self.max_energy.b {
-			self.max_energy.b = self.filtered_energy.b;
-		}
-
-		self.max_energy.w *= COOLDOWN_FACTOR;
-		if self.filtered_energy.w > self.max_energy.w {
-			self.max_energy.w = self.filtered_energy.w;
-		}
-
-		// track the minimum energy with warmup
-		self.min_energy.r += (1.0 - COOLDOWN_FACTOR) * (self.max_energy.r * 0.5 - self.min_energy.r);
-		if self.filtered_energy.r < self.min_energy.r {
-			self.min_energy.r = self.filtered_energy.r;
-		}
+		// normalize the filtered levels
+		let mut normalized = self.filtered_energy;
 
-		self.min_energy.g += (1.0 - COOLDOWN_FACTOR) * (self.max_energy.g * 0.5 - self.min_energy.g);
-		if self.filtered_energy.g < self.min_energy.g {
-			self.min_energy.g = self.filtered_energy.g;
-		}
-
-		self.min_energy.b += (1.0 - COOLDOWN_FACTOR) * (self.max_energy.b * 0.5 - self.min_energy.b);
-		if self.filtered_energy.b < self.min_energy.b {
-			self.min_energy.b = self.filtered_energy.b;
-		}
-
-		self.min_energy.w += (1.0 - COOLDOWN_FACTOR) * (self.max_energy.w * 0.5 - self.min_energy.w);
-		if self.filtered_energy.w < self.min_energy.w {
-			self.min_energy.w = self.filtered_energy.w;
+		for (coloridx, agc) in self.agc.iter_mut().enumerate() {
+			let energy = normalized.ref_by_index_mut(coloridx).unwrap();
+			*energy = agc.update(*energy);
 		}
 
 		// set all LEDs initially to black
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				//self.colorlists[strip][led].scale(FADE_FACTOR);
-				self.colorlists[strip][led] = Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0};
-			}
-		}
+		//self.colorlists.iter_mut().for_each(|c| c.scale(FADE_FACTOR));
+		self.colorlists.fill(Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0});
 
 		// rescaling and normalization of the energies
 		let brightness = Color{
-			r: ((self.filtered_energy.r - self.min_energy.r) / (self.max_energy.r - self.min_energy.r)).powf(RGB_EXPONENT),
-			g: ((self.filtered_energy.g - self.min_energy.g) / (self.max_energy.g - self.min_energy.g)).powf(RGB_EXPONENT),
-			b: ((self.filtered_energy.b - self.min_energy.b) / (self.max_energy.b - self.min_energy.b)).powf(RGB_EXPONENT),
-			w: ((self.filtered_energy.w - self.min_energy.w) / (self.max_energy.w - self.min_energy.w)).powf(W_EXPONENT) * W_SCALE,
+			r: normalized.r.powf(RGB_EXPONENT),
+			g: normalized.g.powf(RGB_EXPONENT),
+			b: normalized.b.powf(RGB_EXPONENT),
+			w: normalized.w.powf(W_EXPONENT) * self.w_scale,
 		};
 
 		// lowpass-filter brightness to reduce intensive fast flashing
@@ -341,15 +368,39 @@ impl Animation for Racers
 			let f = self.filtered_brightness.ref_by_index_mut(i).unwrap();
 			let n = brightness.ref_by_index(i).unwrap();
 
-			*f = (1.0 - BRIGHTNESS_FILTER_ALPHA) * (*f) + BRIGHTNESS_FILTER_ALPHA * (*n);
+			*f = (1.0 - self.brightness_filter_alpha) * (*f) + self.brightness_filter_alpha * (*n);
 		}
 
+		// change direction and pulse with the music
+		let beat_synced = sigproc.get_tempo_confidence() >= BEAT_MIN_CONFIDENCE;
+
+		if beat_synced && sigproc.is_downbeat() {
+			self.racers_r.iter_mut()
+			             .chain(self.racers_g.iter_mut())
+			             .chain(self.racers_b.iter_mut())
+			             .for_each(|x| x.reverse());
+		}
+
+		let pulse = match beat_synced {
+			true  => 1.0 + BEAT_PULSE_SCALE * (-BEAT_PULSE_DECAY * sigproc.get_beat_phase()).exp(),
+			false => 1.0,
+		};
+
 		// update all racers
-		let f = &self.filtered_brightness;
+		let mut f = self.filtered_brightness;
+		f.r *= pulse;
+		f.g *= pulse;
+		f.b *= pulse;
+		let f = &f;
 		let speed = &brightness;
-		self.racers_r.iter_mut().for_each(|x| x.update(speed.r, f.r, f.w));
-		self.racers_g.iter_mut().for_each(|x| x.update(speed.g, f.g, f.w));
-		self.racers_b.iter_mut().for_each(|x| x.update(speed.b, f.b, f.w));
+		let colorlists = &self.colorlists;
+		let layout = self.stereo_layout;
+
+		let weight = |racer: &Racer, balance: f32| animation::stereo_weight(balance, racer.stereo_channel(colorlists, layout));
+
+		self.racers_r.iter_mut().for_each(|x| { let w = weight(x, balance.r); x.update((speed.r * w).min(1.0), f.r * w, f.w) });
+		self.racers_g.iter_mut().for_each(|x| { let w = weight(x, balance.g); x.update((speed.g * w).min(1.0), f.g * w, f.w) });
+		self.racers_b.iter_mut().for_each(|x| { let w = weight(x, balance.b); x.update((speed.b * w).min(1.0), f.b * w, f.w) });
 
 		// render all racers
 		for racer in self.racers_r.iter() {
@@ -365,26 +416,40 @@ impl Animation for Racers
 		}
 
 		// color post-processing
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				self.colorlists[strip][led].limit();
-			}
+		for led in self.colorlists.iter_mut() {
+			led.limit();
 		}
 
 		// debug stuff
 		self.frame_count += 1;
 		if self.frame_count % 100 == 0 {
 			println!("---");
-			print!("Red   "); dbg_bar(self.min_energy.r, self.filtered_brightness.r, self.max_energy.r); println!("{:11.2}", self.max_energy.r);
-			print!("Green "); dbg_bar(self.min_energy.g, self.filtered_brightness.g, self.max_energy.g); println!("{:11.2}", self.max_energy.g);
-			print!("Blue  "); dbg_bar(self.min_energy.b, self.filtered_brightness.b, self.max_energy.b); println!("{:11.2}", self.max_energy.b);
-			print!("White "); dbg_bar(self.min_energy.w, self.filtered_brightness.w, self.max_energy.w); println!("{:11.2}", self.max_energy.w);
+			for (name, coloridx) in [("Red   ", 0), ("Green ", 1), ("Blue  ", 2), ("White ", 3)] {
+				let agc = &self.agc[coloridx];
+				let level = *self.filtered_energy.ref_by_index(coloridx).unwrap();
+
+				print!("{}", name);
+				dbg_bar(agc.noise_floor(), level, agc.reference());
+				println!("{:8.1} dB", agc.reference());
+			}
+		}
+
+		Ok(())
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f32) -> Result<()>
+	{
+		match name {
+			"w_scale"                 => self.w_scale = value,
+			"brightness_filter_alpha" => self.brightness_filter_alpha = animation::alpha_per_frame(value, self.fps_animation),
+			_ if agc::PARAMETERS.contains(&name) => self.agc.iter_mut().for_each(|a| a.set_parameter(name, value)),
+			_ => return Err(AnimationError::ErrorMessage(format!("Unknown parameter: {}", name))),
 		}
 
 		Ok(())
 	}
 
-	fn get_colorlist(&self) -> &[ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS]
+	fn get_colorlist(&self) -> &FrameBuffer
 	{
 		return &self.colorlists;
 	}
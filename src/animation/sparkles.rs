@@ -1,8 +1,10 @@
 // vim: noet
 
-use crate::animation::{Color, Animation, Result};
+use crate::animation::{self, Color, FrameBuffer, Animation, AnimationError, Result};
 use crate::signal_processing::SignalProcessing;
-use crate::config;
+use crate::signal_processing::agc::{self, Agc};
+use crate::signal_processing::onset::OnsetBand;
+use crate::config::Config;
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -10,7 +12,6 @@ use std::collections::VecDeque;
 
 use rand::Rng;
 
-const COOLDOWN_FACTOR     : f32 = 0.99995;
 const RGB_EXPONENT        : f32 = 1.5;
 const W_EXPONENT          : f32 = 2.2;
 const FADE_FACTOR         : f32 = 0.97;
@@ -18,10 +19,16 @@ const AVG_LEDS_ACTIVATED  : f32 = 0.03;
 const WHITE_EXTRA_SCALE   : f32 = 0.3;
 const CONDENSATION_FACTOR : f32 = 5.0;
 
-const SPARK_FADE_STEP     : f32 = 2.500 / config::FPS_ANIMATION;
-const SPARK_VSPEED_MIDS   : f32 = 1.000 * config::NUM_LEDS_PER_STRIP as f32 / config::FPS_ANIMATION;
-const SPARK_VSPEED_HIGHS  : f32 = 0.800 * config::NUM_LEDS_PER_STRIP as f32 / config::FPS_ANIMATION;
-const SPARK_VSPEED_XHIGHS : f32 = 0.500 * config::NUM_LEDS_PER_STRIP as f32 / config::FPS_ANIMATION;
+// brightness per second
+const SPARK_FADE_RATE     : f32 = 2.500;
+
+// strip lengths per second
+const SPARK_VSPEED_MIDS   : f32 = 1.000;
+const SPARK_VSPEED_HIGHS  : f32 = 0.800;
+const SPARK_VSPEED_XHIGHS : f32 = 0.500;
+
+// brightness of the extra sparks on snare and hi-hat onsets per unit of onset strength
+const ONSET_SPARK_SCALE   : f32 = 4.0;
 
 /*
  * A spark is a point of light that can move vertically along the LED strips.
@@ -52,16 +59,16 @@ impl Spark
 		}
 	}
 
-	pub fn update(&mut self)
+	pub fn update(&mut self, fade_step: f32, num_leds_per_strip: usize)
 	{
 		if self.has_expired {
 			return;
 		}
 
 		self.led += self.vspeed;
-		self.brightness -= SPARK_FADE_STEP;
+		self.brightness -= fade_step;
 
-		if (self.led >= config::NUM_LEDS_PER_STRIP as f32) || (self.led <= -1.0) {
+		if (self.led >= num_leds_per_strip as f32) || (self.led <= -1.0) {
 			// moved outside of the LED array -> no need to update this any more
 			self.has_expired = true;
 		}
@@ -77,7 +84,7 @@ impl Spark
 		self.has_expired
 	}
 
-	pub fn render(&self, colorlists: &mut [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS])
+	pub fn render(&self, colorlists: &mut FrameBuffer)
 	{
 		if self.has_expired {
 			// do not render if this Spark has expired
@@ -92,35 +99,62 @@ impl Spark
 		let led1_color = self.color.scaled_copy(fract_led * self.brightness);
 		let led2_color = self.color.scaled_copy((1.0 - fract_led) * self.brightness);
 
+		let strip = colorlists.strip_mut(self.strip as usize);
+
 		if led1_idx >= 0 {
-			colorlists[self.strip as usize][led1_idx as usize].add(&led1_color);
+			strip[led1_idx as usize].add(&led1_color);
 		}
 
-		if led2_idx < config::NUM_LEDS_PER_STRIP {
-			colorlists[self.strip as usize][led2_idx as usize].add(&led2_color);
+		if led2_idx < strip.len() {
+			strip[led2_idx].add(&led2_color);
 		}
 	}
 }
 
 pub struct Sparkles
 {
-	max_energy   : Color,
+	// normalization of the band levels, one per color
+	agc          : Vec<Agc>,
 
 	sparks : VecDeque<Spark>,
 
-	colorlists   : [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+	colorlists   : FrameBuffer,
+
+	// per-frame values derived from the constants above
+	spark_fade_step     : f32,
+	spark_vspeed_mids   : f32,
+	spark_vspeed_highs  : f32,
+	spark_vspeed_xhighs : f32,
+
+	fps_animation      : f32,
+
+	// tunable parameters, see set_parameter()
+	fade_factor        : f32,
+	avg_leds_activated : f32,
+	white_scale        : f32,
 
 	sigproc: Rc<RefCell<SignalProcessing>>,
 }
 
 impl Animation for Sparkles
 {
-	fn new(sigproc: Rc<RefCell<SignalProcessing>>) -> Sparkles
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Sparkles
 	{
+		let fps = config.fps_animation();
+		let strip_len = config.num_leds_per_strip as f32;
+
 		Sparkles {
-			max_energy: Color{r: 1.0, g: 1.0, b: 1.0, w: 1.0},
+			agc: (0..4).map(|_| Agc::new(&config.agc, 1.0 / fps)).collect(),
 			sparks: VecDeque::with_capacity(1024),
-			colorlists: [ [Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			spark_fade_step: SPARK_FADE_RATE / fps,
+			spark_vspeed_mids: SPARK_VSPEED_MIDS * strip_len / fps,
+			spark_vspeed_highs: SPARK_VSPEED_HIGHS * strip_len / fps,
+			spark_vspeed_xhighs: SPARK_VSPEED_XHIGHS * strip_len / fps,
+			fps_animation: fps,
+			fade_factor: animation::decay_per_frame(FADE_FACTOR, fps),
+			avg_leds_activated: AVG_LEDS_ACTIVATED,
+			white_scale: WHITE_EXTRA_SCALE,
 			sigproc: sigproc,
 		}
 	}
@@ -134,51 +168,36 @@ impl Animation for Sparkles
 	{
 		let sigproc = self.sigproc.borrow();
 
-		// extract frequency band energies
-		let cur_energy = Color{
-			r: sigproc.get_energy_in_band(    0.0,   400.0),
-			g: sigproc.get_energy_in_band(  400.0,  4000.0),
-			b: sigproc.get_energy_in_band( 4000.0, 12000.0),
-			w: sigproc.get_energy_in_band(12000.0, 22000.0)};
-
-		// track the maximum energy with cooldown
-		self.max_energy.r *= COOLDOWN_FACTOR;
-		if cur_energy.r > self.max_energy.r {
-			self.max_energy.r = cur_energy.r;
-		}
-
-		self.max_energy.g *= COOLDOWN_FACTOR;
-		if cur_energy.g > self.max_energy.g {
-			self.max_energy.g = cur_energy.g;
-		}
+		// extract the frequency band levels and normalize them
+		let mut cur_energy = Color{
+			r: sigproc.get_level_in_band(    0.0,   400.0),
+			g: sigproc.get_level_in_band(  400.0,  4000.0),
+			b: sigproc.get_level_in_band( 4000.0, 12000.0),
+			w: sigproc.get_level_in_band(12000.0, 22000.0)};
 
-		self.max_energy.b *= COOLDOWN_FACTOR;
-		if cur_energy.b > self.max_energy.b {
-			self.max_energy.b = cur_energy.b;
-		}
-
-		self.max_energy.w *= COOLDOWN_FACTOR;
-		if cur_energy.w > self.max_energy.w {
-			self.max_energy.w = cur_energy.w;
+		for (coloridx, agc) in self.agc.iter_mut().enumerate() {
+			let energy = cur_energy.ref_by_index_mut(coloridx).unwrap();
+			*energy = agc.update(*energy);
 		}
 
 		// fade all LEDs towards black
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				self.colorlists[strip][led].scale(FADE_FACTOR);
-			}
+		for led in self.colorlists.iter_mut() {
+			led.scale(self.fade_factor);
 		}
 
 		// distribute the energy for each color
 		let new_energy = Color{
-			r: (cur_energy.r / self.max_energy.r).powf(RGB_EXPONENT),
-			g: (cur_energy.g / self.max_energy.g).powf(RGB_EXPONENT),
-			b: (cur_energy.b / self.max_energy.b).powf(RGB_EXPONENT),
-			w: (cur_energy.w / self.max_energy.w).powf(W_EXPONENT),
+			r: cur_energy.r.powf(RGB_EXPONENT),
+			g: cur_energy.g.powf(RGB_EXPONENT),
+			b: cur_energy.b.powf(RGB_EXPONENT),
+			w: cur_energy.w.powf(W_EXPONENT),
 		};
 
 		let mut remaining_energy = new_energy.r;
-		remaining_energy *= AVG_LEDS_ACTIVATED * config::NUM_LEDS_TOTAL as f32;
+		remaining_energy *= self.avg_leds_activated * self.colorlists.num_leds_total() as f32;
+
+		let num_strips = self.colorlists.num_strips();
+		let num_leds_per_strip = self.colorlists.num_leds_per_strip();
 
 		let mut rng = rand::thread_rng();
 
@@ -186,8 +205,8 @@ impl Animation for Sparkles
 		while remaining_energy > 0.0 {
 			let mut rnd_energy = rng.gen::<f32>() * new_energy.r * CONDENSATION_FACTOR;
 
-			let rnd_strip = rng.gen_range(0..config::NUM_STRIPS);
-			let rnd_led   = rng.gen_range(0..config::NUM_LEDS_PER_STRIP);
+			let rnd_strip = rng.gen_range(0..num_strips);
+			let rnd_led   = rng.gen_range(0..num_leds_per_strip);
 
 			if rnd_energy > remaining_energy {
 				rnd_energy = remaining_energy;
@@ -196,23 +215,24 @@ impl Animation for Sparkles
 				remaining_energy -= rnd_energy;
 			}
 
-			self.colorlists[rnd_strip][rnd_led].r += rnd_energy;
+			self.colorlists.led_mut(rnd_strip, rnd_led).r += rnd_energy;
 		}
 
 		// update all existing sparks
-		self.sparks.iter_mut().for_each(|x| x.update());
+		let fade_step = self.spark_fade_step;
+		self.sparks.iter_mut().for_each(|x| x.update(fade_step, num_leds_per_strip));
 
 		// Create green sparks for middle frequencies.
 		// They originate in the center and can go both up and down from there.
 		self.sparks.push_back(Spark::new(
 				match rng.gen::<bool>() {
-					true => SPARK_VSPEED_MIDS,
-					false => -SPARK_VSPEED_MIDS,
+					true => self.spark_vspeed_mids,
+					false => -self.spark_vspeed_mids,
 				},
 				new_energy.g,
 				Color{r: 0.0, g: 1.0, b: 0.0, w: 0.0},
-				rng.gen_range(0..config::NUM_STRIPS) as u16,
-				(config::NUM_LEDS_PER_STRIP as f32 / 2.0) - 0.5));
+				rng.gen_range(0..num_strips) as u16,
+				(num_leds_per_strip as f32 / 2.0) - 0.5));
 
 		// Create blue sparks for high frequencies.
 		// They originate either in the top, moving down, or in the bottom, moving up
@@ -220,18 +240,18 @@ impl Animation for Sparkles
 			let start_from_top = rng.gen::<bool>();
 
 			let start_led = match start_from_top {
-				true => config::NUM_LEDS_PER_STRIP-1,
+				true => num_leds_per_strip-1,
 				false => 0} as f32;
 
 			let vspeed = match start_from_top {
-				true => -SPARK_VSPEED_HIGHS,
-				false => SPARK_VSPEED_HIGHS};
+				true => -self.spark_vspeed_highs,
+				false => self.spark_vspeed_highs};
 
 			self.sparks.push_back(Spark::new(
 					vspeed,
 					new_energy.b,
 					Color{r: 0.0, g: 0.0, b: 1.0, w: 0.0},
-					rng.gen_range(0..config::NUM_STRIPS) as u16,
+					rng.gen_range(0..num_strips) as u16,
 					start_led));
 		}
 
@@ -241,21 +261,41 @@ impl Animation for Sparkles
 			let start_from_top = rng.gen::<bool>();
 
 			let start_led = match start_from_top {
-				true => config::NUM_LEDS_PER_STRIP-1,
+				true => num_leds_per_strip-1,
 				false => 0} as f32;
 
 			let vspeed = match start_from_top {
-				true => -SPARK_VSPEED_XHIGHS,
-				false => SPARK_VSPEED_XHIGHS};
+				true => -self.spark_vspeed_xhighs,
+				false => self.spark_vspeed_xhighs};
 
 			self.sparks.push_back(Spark::new(
 					vspeed,
-					new_energy.w * WHITE_EXTRA_SCALE,
+					new_energy.w * self.white_scale,
 					Color{r: 0.0, g: 0.0, b: 0.0, w: 1.0},
-					rng.gen_range(0..config::NUM_STRIPS) as u16,
+					rng.gen_range(0..num_strips) as u16,
 					start_led));
 		}
 
+		// Snare and hi-hat onsets create an additional bright spark in the center of a strip.
+		for (band, color) in [(OnsetBand::Snare, Color{r: 0.0, g: 1.0, b: 0.0, w: 0.0}),
+		                      (OnsetBand::HiHat, Color{r: 0.0, g: 0.0, b: 0.0, w: 1.0})] {
+			if !sigproc.is_onset(band) {
+				continue;
+			}
+
+			let brightness = (sigproc.get_band_onset_strength(band) * ONSET_SPARK_SCALE).min(1.0);
+
+			self.sparks.push_back(Spark::new(
+					match rng.gen::<bool>() {
+						true => self.spark_vspeed_mids,
+						false => -self.spark_vspeed_mids,
+					},
+					brightness,
+					color,
+					rng.gen_range(0..num_strips) as u16,
+					(num_leds_per_strip as f32 / 2.0) - 0.5));
+		}
+
 		// remove expired sparks in the beginning of the deque
 		while self.sparks.front().map_or(false, |s| s.has_expired()) {
 			self.sparks.pop_front();
@@ -267,16 +307,27 @@ impl Animation for Sparkles
 		}
 
 		// color post-processing
-		for strip in 0..config::NUM_STRIPS {
-			for led in 0..config::NUM_LEDS_PER_STRIP {
-				self.colorlists[strip][led].limit();
-			}
+		for led in self.colorlists.iter_mut() {
+			led.limit();
+		}
+
+		Ok(())
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f32) -> Result<()>
+	{
+		match name {
+			"fade_factor"        => self.fade_factor = animation::decay_per_frame(value, self.fps_animation),
+			"avg_leds_activated" => self.avg_leds_activated = value,
+			"white_scale"        => self.white_scale = value,
+			_ if agc::PARAMETERS.contains(&name) => self.agc.iter_mut().for_each(|a| a.set_parameter(name, value)),
+			_ => return Err(AnimationError::ErrorMessage(format!("Unknown parameter: {}", name))),
 		}
 
 		Ok(())
 	}
 
-	fn get_colorlist(&self) -> &[ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS]
+	fn get_colorlist(&self) -> &FrameBuffer
 	{
 		return &self.colorlists;
 	}
@@ -1,8 +1,10 @@
 // vim: noet
 
-use crate::animation::{Color, Animation, Result};
+use crate::animation::{self, Color, FrameBuffer, Animation, AnimationError, Result};
 use crate::signal_processing::SignalProcessing;
-use crate::config;
+use crate::signal_processing::agc::{self, Agc};
+use crate::signal_processing::silence::MIN_LEVEL;
+use crate::config::Config;
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -19,21 +21,25 @@ const CONDENSATION_FACTOR : f32 = 5.0;
 
 pub struct Spectrum
 {
-	colorlists   : [ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
-	energies: [f32; config::NUM_LEDS_TOTAL],
+	colorlists   : FrameBuffer,
+	energies: Vec<f32>,
 	sigproc: Rc<RefCell<SignalProcessing>>,
-	max_energy: f32,
+	agc: Agc,
+	cooldown_factor: f32,
+	fps_animation: f32,
 }
 
 impl Animation for Spectrum
 {
-	fn new(sigproc: Rc<RefCell<SignalProcessing>>) -> Spectrum
+	fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Spectrum
 	{
 		Spectrum {
-			colorlists: [ [Color{r: 0.0, g: 0.0, b: 0.0, w: 0.0}; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS],
-			energies: [0.0; config::NUM_LEDS_TOTAL],
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			energies: vec![0.0; config.num_leds_total()],
 			sigproc,
-			max_energy: 1.0,
+			agc: Agc::new(&config.agc, 1.0 / config.fps_animation()),
+			cooldown_factor: animation::decay_per_frame(COOLDOWN_FACTOR, config.fps_animation()),
+			fps_animation: config.fps_animation(),
 		}
 	}
 
@@ -46,43 +52,48 @@ impl Animation for Spectrum
 	{
 		let sigproc = self.sigproc.borrow();
 
-		let max_energy = self.max_energy;
+		let num_leds_total = self.colorlists.num_leds_total();
+		let num_leds_per_strip = self.colorlists.num_leds_per_strip();
 
-		for led in 0..config::NUM_LEDS_TOTAL
-		{
-			let led0_f32 = led as f32 / config::NUM_LEDS_TOTAL as f32;
-			let led1_f32 = (led+1) as f32 / config::NUM_LEDS_TOTAL as f32;
-			let energy = sigproc.get_energy_in_band( f32::powf(5000., led0_f32 * 0.6 + 1.0 - 0.6) , f32::powf(5000., led1_f32 * 0.6 + 1.0 - 0.6));
-
-			let mut pitch0 = (led0_f32 * 36.0);
-			while pitch0 > 12.0 {
-				pitch0 -= 12.0;
-			}
-			let pitch1 = pitch0 + (1.0 / config::NUM_LEDS_TOTAL as f32 * 36.0);
+		let num_bands = sigproc.get_num_bands();
 
-			let mut total_energy = 0.0;
-			for octave in (f32::log2(400.0) as u32)..(f32::log2(5000.0) as u32) {
-				let base_freq = f32::powf(2.0, octave as f32);
-				let energy = sigproc.get_energy_in_band(base_freq * f32::powf(2.0, pitch0 / 12.0), base_freq * f32::powf(2.0, pitch1 / 12.0));
-				total_energy += energy;
-			}
+		// all bands share one normalization, which follows the loudest band
+		let loudest = (0..num_bands).map(|band| sigproc.get_band_level(band))
+		                            .fold(MIN_LEVEL, f32::max);
 
-			//let total_energy = energy;
+		self.agc.update(loudest);
 
+		for led in 0..num_leds_total
+		{
+			// each LED shows the filterbank level at its position, interpolated between the bands
+			let pos = (led as f32 + 0.5) / num_leds_total as f32 * num_bands as f32 - 0.5;
+			let band = (pos.max(0.0) as usize).min(num_bands - 1);
+			let next = (band + 1).min(num_bands - 1);
+			let frac = (pos - band as f32).clamp(0.0, 1.0);
 
-			self.energies[led] = (COOLDOWN_FACTOR * self.energies[led]).max(total_energy);
+			let level = (1.0 - frac) * sigproc.get_band_level(band) + frac * sigproc.get_band_level(next);
+			let total_energy = self.agc.normalize(level);
 
-			self.colorlists[0][led] = palette( (self.energies[led] / max_energy).powf(3.0) );
+			self.energies[led] = (self.cooldown_factor * self.energies[led]).max(total_energy);
 
-			self.max_energy = self.max_energy.max(total_energy);
+			*self.colorlists.led_mut(led / num_leds_per_strip, led % num_leds_per_strip) = palette( self.energies[led].powf(3.0) );
 		}
 
-		self.max_energy *= 0.997;
+		Ok(())
+	}
+
+	fn set_parameter(&mut self, name: &str, value: f32) -> Result<()>
+	{
+		match name {
+			"cooldown_factor" => self.cooldown_factor = animation::decay_per_frame(value, self.fps_animation),
+			_ if agc::PARAMETERS.contains(&name) => self.agc.set_parameter(name, value),
+			_ => return Err(AnimationError::ErrorMessage(format!("Unknown parameter: {}", name))),
+		}
 
 		Ok(())
 	}
 
-	fn get_colorlist(&self) -> &[ [Color; config::NUM_LEDS_PER_STRIP]; config::NUM_STRIPS]
+	fn get_colorlist(&self) -> &FrameBuffer
 	{
 		return &self.colorlists;
 	}
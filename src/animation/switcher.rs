@@ -0,0 +1,238 @@
+// vim: noet
+
+use crate::animation::{self, FrameBuffer, Animation, Parameters, Result};
+use crate::signal_processing::SignalProcessing;
+use crate::config::Config;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/*
+ * The animation that is faded out. If another switch happens during a crossfade, the mix shown at
+ * that moment is frozen and faded out instead, so the display does not jump.
+ */
+enum Outgoing
+{
+	Running(Box<dyn Animation>),
+	Frozen(FrameBuffer),
+}
+
+/*
+ * The switcher owns the running animation and allows replacing it at runtime. During a switch,
+ * the outgoing and the incoming animation both keep running and their colorlists are crossfaded.
+ */
+pub struct Switcher
+{
+	current      : Box<dyn Animation>,
+	current_name : String,
+
+	// outgoing animation, only present while a crossfade is in progress
+	previous     : Option<Outgoing>,
+
+	fade_frames  : usize,
+	fade_pos     : usize,
+
+	colorlists   : FrameBuffer,
+
+	config: Config,
+
+	sigproc: Rc<RefCell<SignalProcessing>>,
+}
+
+impl Switcher
+{
+	/// Create a switcher running the animation selected in the configuration.
+	pub fn new(sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Result<Switcher>
+	{
+		let mut current = animation::create(&config.animation, sigproc.clone(), config)?;
+		current.init()?;
+
+		Ok(Switcher {
+			current,
+			current_name: config.animation.clone(),
+			previous: None,
+			fade_frames: (config.crossfade_duration * config.fps_animation()) as usize,
+			fade_pos: 0,
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			config: config.clone(),
+			sigproc,
+		})
+	}
+
+	pub fn current_name(&self) -> &str
+	{
+		&self.current_name
+	}
+
+	/// Start a crossfade to the animation with the given name and apply the given parameters. If
+	/// the animation is already running, only the parameters are applied. Returns whether the
+	/// animation was switched.
+	pub fn switch_to(&mut self, name: &str, params: &Parameters) -> Result<bool>
+	{
+		if name == self.current_name {
+			for (param, value) in params.iter() {
+				self.current.set_parameter(param, *value)?;
+			}
+
+			return Ok(false);
+		}
+
+		let mut next = animation::create(name, self.sigproc.clone(), &self.config)?;
+		next.init()?;
+
+		for (param, value) in params.iter() {
+			next.set_parameter(param, *value)?;
+		}
+
+		// the mix shown at this moment, which is frozen if a crossfade is running
+		self.update_mix();
+
+		let outgoing = std::mem::replace(&mut self.current, next);
+
+		self.current_name = name.to_string();
+
+		self.previous = match self.previous.take() {
+			_ if self.fade_frames == 0 => None,
+			Some(_) => Some(Outgoing::Frozen(self.colorlists.clone())),
+			None    => Some(Outgoing::Running(outgoing)),
+		};
+
+		self.fade_pos = 0;
+		self.update_mix();
+
+		Ok(true)
+	}
+
+	/// Switch to the animation following the current one in `animation::AVAILABLE`.
+	pub fn next(&mut self) -> Result<bool>
+	{
+		let mut names = animation::names();
+
+		// without configured layers, the "layers" animation would stay black
+		if self.config.layers.is_empty() {
+			names.retain(|n| *n != "layers");
+		}
+
+		let idx = names.iter()
+		               .position(|n| *n == self.current_name)
+		               .map_or(0, |i| (i + 1) % names.len());
+
+		self.switch_to(names[idx], &Parameters::new())
+	}
+
+	pub fn periodic(&mut self) -> Result<()>
+	{
+		self.current.periodic()?;
+
+		if let Some(Outgoing::Running(anim)) = self.previous.as_mut() {
+			anim.periodic()?;
+		}
+
+		if self.previous.is_some() {
+			self.fade_pos += 1;
+			self.update_mix();
+
+			if self.fade_pos >= self.fade_frames {
+				self.previous = None;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Mix the outgoing and the current colorlist at the current position of the crossfade.
+	fn update_mix(&mut self)
+	{
+		let prev_colorlists = match &self.previous {
+			Some(Outgoing::Running(anim))      => anim.get_colorlist(),
+			Some(Outgoing::Frozen(colorlists)) => colorlists,
+			None                               => return,
+		};
+
+		let factor = self.fade_pos as f32 / self.fade_frames as f32;
+
+		self.colorlists.iter_mut()
+		               .zip(prev_colorlists.iter().zip(self.current.get_colorlist().iter()))
+		               .for_each(|(out, (prev, cur))| *out = prev.mix(cur, factor));
+	}
+
+	pub fn get_colorlist(&self) -> &FrameBuffer
+	{
+		match self.previous {
+			Some(_) => &self.colorlists,
+			None    => self.current.get_colorlist(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	use crate::animation::Color;
+
+	/*
+	 * Shows a fixed frame.
+	 */
+	struct Still
+	{
+		colorlists: FrameBuffer,
+	}
+
+	impl Animation for Still
+	{
+		fn new(_sigproc: Rc<RefCell<SignalProcessing>>, config: &Config) -> Still
+		{
+			Still {
+				colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			}
+		}
+
+		fn init(&mut self) -> Result<()>
+		{
+			Ok(())
+		}
+
+		fn periodic(&mut self) -> Result<()>
+		{
+			Ok(())
+		}
+
+		fn get_colorlist(&self) -> &FrameBuffer
+		{
+			&self.colorlists
+		}
+	}
+
+	#[test]
+	fn switching_twice_freezes_the_shown_frame()
+	{
+		let config = Config::default();
+		let sigproc = Rc::new(RefCell::new(SignalProcessing::new(&config).unwrap()));
+
+		let mut still = Still::new(sigproc.clone(), &config);
+		still.colorlists.fill(Color{r: 1.0, g: 0.5, b: 0.0, w: 0.0});
+
+		let mut switcher = Switcher {
+			current: Box::new(still),
+			current_name: "still".to_string(),
+			previous: None,
+			fade_frames: 10,
+			fade_pos: 0,
+			colorlists: FrameBuffer::new(config.num_strips, config.num_leds_per_strip),
+			config: config.clone(),
+			sigproc,
+		};
+
+		// no periodic() call in between, e.g. in standby
+		assert!(switcher.switch_to("particles", &Parameters::new()).unwrap());
+		assert!(switcher.switch_to("sparkles", &Parameters::new()).unwrap());
+
+		assert!(matches!(switcher.previous, Some(Outgoing::Frozen(_))));
+
+		for c in switcher.get_colorlist().iter() {
+			assert_eq!((c.r, c.g, c.b, c.w), (1.0, 0.5, 0.0, 0.0));
+		}
+	}
+}
@@ -0,0 +1,47 @@
+// vim: noet
+
+use crate::config::Config;
+
+pub mod stdin;
+pub mod file;
+pub mod resample;
+pub mod network;
+
+/////////// Audio Source Trait ////////////
+
+/// One sample of the left and the right channel. Mono inputs deliver the same sample on both.
+pub type Frame = [i16; 2];
+
+/*
+ * An audio source delivers the input as stereo frames. Sources that read from a pipe are paced by
+ * the sender and the network source by its sample clock; file sources pace themselves if requested.
+ */
+pub trait AudioSource {
+	/// Read the next frame. Returns `Ok(None)` at the end of the input.
+	fn read_frame(&mut self) -> std::io::Result<Option<Frame>>;
+
+	/// Sample rate of the input, if the source knows it.
+	fn sample_rate(&self) -> Option<f32>
+	{
+		None
+	}
+}
+
+/// Construct the audio source selected in the configuration. If a resampling rate is configured,
+/// the input is converted to it.
+pub fn create(config: &Config) -> std::io::Result<Box<dyn AudioSource>>
+{
+	let source: Box<dyn AudioSource> = match (&config.input_file, &config.input_network) {
+		(Some(path), _)    => Box::new(file::FileSource::open(path, config.input_realtime)?),
+		(None, Some(addr)) => Box::new(network::NetworkSource::new(addr, config.input_network_format, config.input_channels,
+		                                                           config.samp_rate, config.jitter_buffer)?),
+		(None, None)       => Box::new(stdin::StdinSource::new(config.input_format, config.input_channels)),
+	};
+
+	let in_rate = source.sample_rate().unwrap_or(config.samp_rate);
+
+	match config.resample_rate {
+		Some(rate) if rate != in_rate => Ok(Box::new(resample::Resampler::new(source, in_rate, rate))),
+		_                             => Ok(source),
+	}
+}
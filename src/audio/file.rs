@@ -0,0 +1,186 @@
+// vim: noet
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::audio::{AudioSource, Frame};
+
+// when pacing, the timing is checked every this many frames
+const PACING_INTERVAL: u64 = 256;
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> std::io::Error
+{
+	std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Scale an integer sample with the given number of bits to 16 bits.
+fn to_i16(sample: i32, bits: u32) -> i16
+{
+	if bits >= 16 {
+		(sample >> (bits - 16)) as i16
+	} else {
+		(sample << (16 - bits)) as i16
+	}
+}
+
+/*
+ * Decodes a FLAC file block by block and returns the samples interleaved.
+ */
+struct FlacSamples
+{
+	reader: claxon::FlacReader<File>,
+	block:  Option<claxon::Block>,
+	bits:   u32,
+	pos:    u32,
+}
+
+impl FlacSamples
+{
+	fn next_sample(&mut self) -> claxon::Result<Option<i16>>
+	{
+		loop {
+			if let Some(block) = &self.block {
+				if self.pos < block.len() {
+					let channels = block.channels();
+					let sample = block.sample(self.pos % channels, self.pos / channels);
+
+					self.pos += 1;
+
+					return Ok(Some(to_i16(sample, self.bits)));
+				}
+			}
+
+			// reuse the buffer of the previous block
+			let buffer = self.block.take().map_or_else(Vec::new, |b| b.into_buffer());
+
+			match self.reader.blocks().read_next_or_eof(buffer)? {
+				Some(block) => self.block = Some(block),
+				None        => return Ok(None),
+			}
+
+			self.pos = 0;
+		}
+	}
+}
+
+impl Iterator for FlacSamples
+{
+	type Item = std::io::Result<i16>;
+
+	fn next(&mut self) -> Option<Self::Item>
+	{
+		self.next_sample().map_err(invalid_data).transpose()
+	}
+}
+
+/*
+ * Plays a WAV or FLAC file. Only the first two channels are used. If `realtime` is set, samples
+ * are delivered at the sample rate of the file; otherwise the file is processed as fast as possible.
+ */
+pub struct FileSource
+{
+	samples: Box<dyn Iterator<Item = std::io::Result<i16>>>,
+
+	channels:    u32,
+	sample_rate: u32,
+
+	realtime:    bool,
+	start:       Option<Instant>,
+	frames_read: u64,
+}
+
+impl FileSource
+{
+	pub fn open(path: &str, realtime: bool) -> std::io::Result<FileSource>
+	{
+		let is_flac = Path::new(path).extension()
+		                             .is_some_and(|e| e.eq_ignore_ascii_case("flac"));
+
+		let (samples, channels, sample_rate): (Box<dyn Iterator<Item = std::io::Result<i16>>>, u32, u32) = if is_flac {
+			let reader = claxon::FlacReader::open(path).map_err(invalid_data)?;
+			let info = reader.streaminfo();
+
+			(Box::new(FlacSamples { reader, block: None, bits: info.bits_per_sample, pos: 0 }),
+			 info.channels, info.sample_rate)
+		} else {
+			let reader = hound::WavReader::new(BufReader::new(File::open(path)?)).map_err(invalid_data)?;
+			let spec = reader.spec();
+
+			let samples: Box<dyn Iterator<Item = std::io::Result<i16>>> = match spec.sample_format {
+				hound::SampleFormat::Int => {
+					let bits = spec.bits_per_sample as u32;
+					Box::new(reader.into_samples::<i32>().map(move |s| s.map(|s| to_i16(s, bits)).map_err(invalid_data)))
+				},
+				hound::SampleFormat::Float => {
+					Box::new(reader.into_samples::<f32>().map(|s| s.map(|s| (s.clamp(-1.0, 1.0) * 32767.0) as i16).map_err(invalid_data)))
+				},
+			};
+
+			(samples, spec.channels as u32, spec.sample_rate)
+		};
+
+		Ok(FileSource {
+			samples,
+			channels,
+			sample_rate,
+			realtime,
+			start: None,
+			frames_read: 0,
+		})
+	}
+
+	/// Wait until the current position in the file is reached in real time.
+	fn pace(&mut self)
+	{
+		let start = *self.start.get_or_insert_with(Instant::now);
+
+		if self.frames_read.is_multiple_of(PACING_INTERVAL) {
+			let due = start + Duration::from_secs_f64(self.frames_read as f64 / self.sample_rate as f64);
+			let now = Instant::now();
+
+			if due > now {
+				sleep(due - now);
+			}
+		}
+	}
+}
+
+impl AudioSource for FileSource
+{
+	fn read_frame(&mut self) -> std::io::Result<Option<Frame>>
+	{
+		if self.realtime {
+			self.pace();
+		}
+
+		let mut frame = [0; 2];
+
+		for ch in 0 .. self.channels as usize {
+			let sample = match self.samples.next() {
+				Some(s) => s?,
+				None    => return Ok(None),
+			};
+
+			// further channels are skipped
+			if let Some(s) = frame.get_mut(ch) {
+				*s = sample;
+			}
+		}
+
+		if self.channels == 1 {
+			frame[1] = frame[0];
+		}
+
+		self.frames_read += 1;
+
+		Ok(Some(frame))
+	}
+
+	fn sample_rate(&self) -> Option<f32>
+	{
+		Some(self.sample_rate as f32)
+	}
+}
@@ -0,0 +1,427 @@
+// vim: noet
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use crate::audio::{AudioSource, Frame};
+use crate::config::NetworkFormat;
+
+const MAX_DATAGRAM_LEN: usize = 65536;
+
+const RTP_VERSION: u8        = 2;
+const RTP_HEADER_LEN: usize  = 12;
+const FRAMED_HEADER_LEN: usize = 4;
+
+// packet length assumed before the first packet is received
+const DEFAULT_PACKET_FRAMES: usize = 256;
+
+// the jitter buffer is flushed down to its target depth if it grows beyond this factor
+const MAX_DEPTH_FACTOR: usize = 4;
+
+/*
+ * One received packet with its extended (non-wrapping) sequence number.
+ */
+struct Packet
+{
+	seq:    u64,
+	frames: Vec<Frame>,
+}
+
+/// Extend a sequence number with the given number of bits to 64 bits, choosing the value closest
+/// to the expected one.
+fn extend_seq(seq: u32, bits: u32, expected: u64) -> u64
+{
+	let modulus = 1i64 << bits;
+	let diff = (seq as i64 - expected as i64).rem_euclid(modulus);
+	let diff = if diff >= modulus / 2 { diff - modulus } else { diff };
+
+	(expected as i64 + diff).max(0) as u64
+}
+
+fn decode_frames(payload: &[u8], channels: usize, big_endian: bool) -> Vec<Frame>
+{
+	payload.chunks_exact(2 * channels)
+	       .map(|chunk| {
+	           let sample = |i: usize| {
+	               let bytes = [chunk[2 * i], chunk[2 * i + 1]];
+	               if big_endian { i16::from_be_bytes(bytes) } else { i16::from_le_bytes(bytes) }
+	           };
+
+	           match channels {
+	               1 => [sample(0), sample(0)],
+	               _ => [sample(0), sample(1)],
+	           }
+	       })
+	       .collect()
+}
+
+/// Parse an RTP packet with an L16 payload. Returns the sequence number, the SSRC and the payload.
+fn parse_rtp(data: &[u8]) -> Option<(u32, u32, &[u8])>
+{
+	if data.len() < RTP_HEADER_LEN || data[0] >> 6 != RTP_VERSION {
+		return None;
+	}
+
+	let padding = data[0] & 0x20 != 0;
+	let extension = data[0] & 0x10 != 0;
+	let csrc_count = (data[0] & 0x0f) as usize;
+
+	let seq = u16::from_be_bytes([data[2], data[3]]) as u32;
+	let ssrc = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+
+	let mut start = RTP_HEADER_LEN + 4 * csrc_count;
+
+	if extension {
+		let ext_len = u16::from_be_bytes([*data.get(start + 2)?, *data.get(start + 3)?]) as usize;
+		start += 4 + 4 * ext_len;
+	}
+
+	let mut end = data.len();
+
+	if padding {
+		end = end.checked_sub(*data.last()? as usize)?;
+	}
+
+	if start > end {
+		return None;
+	}
+
+	Some((seq, ssrc, &data[start .. end]))
+}
+
+/*
+ * Receives PCM audio over UDP, either as RTP with an L16 payload (e.g. from PulseAudio's
+ * module-rtp-send) or in a simple framed format: a 32 bit little-endian sequence number followed
+ * by interleaved s16le samples.
+ *
+ * Packets are reordered in a jitter buffer. Playback starts once the buffer holds `jitter_buffer`
+ * seconds of audio and is paced by the sample clock, so the buffer is not drained when the frames
+ * are read faster than in real time. A lost packet is replaced by the previous one at half the
+ * amplitude; further losses and gaps in the stream are filled with silence.
+ */
+pub struct NetworkSource
+{
+	socket:   UdpSocket,
+	format:   NetworkFormat,
+	channels: usize,
+
+	sample_rate:   f32,
+	target_frames: usize,
+
+	buffer:    BTreeMap<u64, Vec<Frame>>,
+	buffering: bool,
+	next_seq:  Option<u64>,
+	ssrc:      Option<u32>,
+
+	current:     VecDeque<Frame>,
+	last_packet: Vec<Frame>,
+	concealed:   bool,
+
+	// time at which the next packet is due by the sample clock
+	next_output: Option<Instant>,
+
+	recv_buf: Vec<u8>,
+}
+
+impl NetworkSource
+{
+	pub fn new(bind_address: &str, format: NetworkFormat, channels: usize, sample_rate: f32, jitter_buffer: f32) -> std::io::Result<NetworkSource>
+	{
+		let socket = UdpSocket::bind(bind_address)?;
+
+		Ok(NetworkSource {
+			socket,
+			format,
+			channels,
+			sample_rate,
+			target_frames: ((jitter_buffer * sample_rate) as usize).max(1),
+			buffer: BTreeMap::new(),
+			buffering: true,
+			next_seq: None,
+			ssrc: None,
+			current: VecDeque::new(),
+			last_packet: Vec::new(),
+			concealed: false,
+			next_output: None,
+			recv_buf: vec![0; MAX_DATAGRAM_LEN],
+		})
+	}
+
+	fn buffered_frames(&self) -> usize
+	{
+		self.buffer.values().map(|p| p.len()).sum()
+	}
+
+	/// Length of one packet in frames, as estimated from the last one.
+	fn packet_frames(&self) -> usize
+	{
+		match self.last_packet.len() {
+			0 => DEFAULT_PACKET_FRAMES,
+			n => n,
+		}
+	}
+
+	fn packet_duration(&self) -> Duration
+	{
+		Duration::from_secs_f32(self.packet_frames() as f32 / self.sample_rate)
+	}
+
+	fn push_silence(&mut self)
+	{
+		let frames = self.packet_frames();
+		self.current.extend(std::iter::repeat_n([0; 2], frames));
+	}
+
+	fn reset(&mut self)
+	{
+		self.buffer.clear();
+		self.buffering = true;
+		self.next_seq = None;
+	}
+
+	fn parse(&mut self, len: usize) -> Option<Packet>
+	{
+		let data = &self.recv_buf[.. len];
+
+		let (seq, bits, frames, ssrc) = match self.format {
+			NetworkFormat::Rtp => {
+				let (seq, ssrc, payload) = parse_rtp(data)?;
+				(seq, 16, decode_frames(payload, self.channels, true), Some(ssrc))
+			},
+			NetworkFormat::Framed => {
+				if data.len() < FRAMED_HEADER_LEN {
+					return None;
+				}
+
+				let seq = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+
+				(seq, 32, decode_frames(&data[FRAMED_HEADER_LEN ..], self.channels, false), None)
+			},
+		};
+
+		// a new sender starts a new stream
+		if let Some(ssrc) = ssrc {
+			if self.ssrc.is_some_and(|s| s != ssrc) {
+				self.reset();
+			}
+
+			self.ssrc = Some(ssrc);
+		}
+
+		let expected = self.next_seq.or_else(|| self.buffer.keys().next().copied())
+		                            .unwrap_or(seq as u64);
+
+		Some(Packet {
+			seq: extend_seq(seq, bits, expected),
+			frames,
+		})
+	}
+
+	/// Receive packets into the jitter buffer. Waits up to `timeout` for the first packet and
+	/// then takes everything that is already queued.
+	fn receive(&mut self, timeout: Duration) -> std::io::Result<()>
+	{
+		self.socket.set_read_timeout(Some(timeout))?;
+
+		loop {
+			let len = match self.socket.recv(&mut self.recv_buf) {
+				Ok(len) => len,
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => break,
+				Err(e) => return Err(e),
+			};
+
+			if let Some(packet) = self.parse(len) {
+				// drop packets that were already played or concealed
+				if self.next_seq.is_none_or(|n| packet.seq >= n) && !packet.frames.is_empty() {
+					self.buffer.insert(packet.seq, packet.frames);
+				}
+			}
+
+			// only block for the first packet
+			self.socket.set_read_timeout(Some(Duration::from_micros(1)))?;
+		}
+
+		// limit the latency if the sender is faster than the playback
+		if self.buffered_frames() > MAX_DEPTH_FACTOR * self.target_frames {
+			while let Some(first) = self.buffer.first_key_value().map(|(_, p)| p.len()) {
+				if self.buffered_frames() - first < self.target_frames {
+					break;
+				}
+
+				self.buffer.pop_first();
+			}
+
+			self.next_seq = self.buffer.keys().next().copied();
+		}
+
+		Ok(())
+	}
+
+	/// Wait until the next packet is due by the sample clock, receiving packets in the meantime.
+	fn wait_for_clock(&mut self) -> std::io::Result<()>
+	{
+		let now = Instant::now();
+		let max_lag = Duration::from_secs_f32(self.target_frames as f32 / self.sample_rate);
+
+		let due = match self.next_output {
+			// if the reader fell behind by more than the jitter buffer, do not try to catch up
+			Some(t) if t + max_lag >= now => t,
+			_ => now,
+		};
+
+		self.next_output = Some(due);
+
+		loop {
+			let now = Instant::now();
+
+			if now >= due {
+				return Ok(());
+			}
+
+			self.receive(due - now)?;
+		}
+	}
+
+	/// Fill `current` with the next packet or its replacement.
+	fn next_packet(&mut self) -> std::io::Result<()>
+	{
+		let packet_duration = self.packet_duration();
+
+		if self.buffering {
+			self.receive(Duration::from_micros(1))?;
+
+			if self.buffered_frames() < self.target_frames {
+				// keep the pipeline running with silence while waiting for the stream
+				self.push_silence();
+				return Ok(());
+			}
+
+			self.buffering = false;
+			self.next_seq = self.buffer.keys().next().copied();
+		}
+
+		let next_seq = match self.next_seq {
+			Some(n) => n,
+			None    => { self.reset(); return Ok(()); },
+		};
+
+		// wait up to one packet duration for a missing packet
+		if !self.buffer.contains_key(&next_seq) {
+			self.receive(packet_duration)?;
+		} else {
+			self.receive(Duration::from_micros(1))?;
+		}
+
+		match self.buffer.remove(&next_seq) {
+			Some(frames) => {
+				self.current.extend(frames.iter().copied());
+				self.last_packet = frames;
+				self.concealed = false;
+			},
+			None if self.buffer.is_empty() => {
+				// the stream stopped: wait for it to fill the jitter buffer again
+				self.reset();
+				self.push_silence();
+			},
+			None => {
+				// the packet was lost: conceal it
+				if self.concealed {
+					self.push_silence();
+				} else {
+					self.current.extend(self.last_packet.iter().map(|[l, r]| [l / 2, r / 2]));
+					self.concealed = true;
+				}
+			},
+		}
+
+		self.next_seq = self.next_seq.map(|n| n + 1);
+
+		Ok(())
+	}
+}
+
+impl AudioSource for NetworkSource
+{
+	fn read_frame(&mut self) -> std::io::Result<Option<Frame>>
+	{
+		while self.current.is_empty() {
+			self.wait_for_clock()?;
+			self.next_packet()?;
+
+			let duration = Duration::from_secs_f32(self.current.len() as f32 / self.sample_rate);
+			self.next_output = self.next_output.map(|t| t + duration);
+		}
+
+		Ok(self.current.pop_front())
+	}
+
+	fn sample_rate(&self) -> Option<f32>
+	{
+		Some(self.sample_rate)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	const SAMPLE_RATE: f32     = 8000.0;
+	const PACKET_FRAMES: usize = 8;
+
+	fn frame(seq: u32, i: usize) -> Frame
+	{
+		let value = (seq * 100 + i as u32) as i16;
+		[value, -value]
+	}
+
+	fn framed_packet(seq: u32) -> Vec<u8>
+	{
+		let mut packet = seq.to_le_bytes().to_vec();
+
+		for i in 0 .. PACKET_FRAMES {
+			for sample in frame(seq, i) {
+				packet.extend_from_slice(&sample.to_le_bytes());
+			}
+		}
+
+		packet
+	}
+
+	#[test]
+	fn reorders_and_conceals_packets()
+	{
+		// the jitter buffer holds four packets
+		let jitter_buffer = 4.0 * PACKET_FRAMES as f32 / SAMPLE_RATE;
+		let mut source = NetworkSource::new("127.0.0.1:0", NetworkFormat::Framed, 2, SAMPLE_RATE, jitter_buffer).unwrap();
+
+		let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+		sender.connect(source.socket.local_addr().unwrap()).unwrap();
+
+		// packet 1 arrives late and packet 4 is lost
+		for seq in [0, 2, 1, 3, 5, 6] {
+			sender.send(&framed_packet(seq)).unwrap();
+		}
+
+		let start = Instant::now();
+
+		let frames: Vec<Frame> = (0 .. 7 * PACKET_FRAMES).map(|_| source.read_frame().unwrap().unwrap())
+		                                                 .collect();
+
+		for (seq, packet) in frames.chunks(PACKET_FRAMES).enumerate() {
+			for (i, f) in packet.iter().enumerate() {
+				let expected = match seq {
+					// the previous packet at half the amplitude
+					4 => frame(3, i).map(|s| s / 2),
+					_ => frame(seq as u32, i),
+				};
+
+				assert_eq!(*f, expected, "packet {}, frame {}", seq, i);
+			}
+		}
+
+		// the last packet is only released after the first six have been played
+		assert!(start.elapsed().as_secs_f32() >= 6.0 * PACKET_FRAMES as f32 / SAMPLE_RATE);
+	}
+}
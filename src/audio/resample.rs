@@ -0,0 +1,80 @@
+// vim: noet
+
+use crate::audio::{AudioSource, Frame};
+
+/*
+ * Converts the frames of another source to a different sample rate by linear interpolation. This
+ * is not suitable for high-quality audio, but fine for the analysis of the spectrum.
+ */
+pub struct Resampler
+{
+	source: Box<dyn AudioSource>,
+
+	// input frames per output frame
+	step: f64,
+
+	// position between prev and next, in input frames
+	pos:  f64,
+	prev: Frame,
+	next: Frame,
+
+	started:  bool,
+	out_rate: f32,
+}
+
+impl Resampler
+{
+	pub fn new(source: Box<dyn AudioSource>, in_rate: f32, out_rate: f32) -> Resampler
+	{
+		Resampler {
+			source,
+			step: in_rate as f64 / out_rate as f64,
+			pos: 0.0,
+			prev: [0; 2],
+			next: [0; 2],
+			started: false,
+			out_rate,
+		}
+	}
+}
+
+impl AudioSource for Resampler
+{
+	fn read_frame(&mut self) -> std::io::Result<Option<Frame>>
+	{
+		if !self.started {
+			self.next = match self.source.read_frame()? {
+				Some(f) => f,
+				None    => return Ok(None),
+			};
+
+			self.pos = 1.0;
+			self.started = true;
+		}
+
+		while self.pos >= 1.0 {
+			self.prev = self.next;
+			self.next = match self.source.read_frame()? {
+				Some(f) => f,
+				None    => return Ok(None),
+			};
+
+			self.pos -= 1.0;
+		}
+
+		let mut frame = [0; 2];
+
+		for (out, (p, n)) in frame.iter_mut().zip(self.prev.iter().zip(self.next.iter())) {
+			*out = (*p as f64 + (*n as f64 - *p as f64) * self.pos).round() as i16;
+		}
+
+		self.pos += self.step;
+
+		Ok(Some(frame))
+	}
+
+	fn sample_rate(&self) -> Option<f32>
+	{
+		Some(self.out_rate)
+	}
+}
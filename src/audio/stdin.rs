@@ -0,0 +1,71 @@
+// vim: noet
+
+use byteorder::{BigEndian, LittleEndian, NativeEndian, ReadBytesExt};
+
+use crate::audio::{AudioSource, Frame};
+use crate::config::SampleFormat;
+
+/*
+ * Reads raw interleaved samples from stdin, e.g. from `parec` or `pw-record`. All formats are
+ * converted to 16 bit.
+ */
+pub struct StdinSource
+{
+	stdin:    std::io::Stdin,
+	format:   SampleFormat,
+	channels: usize,
+}
+
+impl StdinSource
+{
+	pub fn new(format: SampleFormat, channels: usize) -> StdinSource
+	{
+		StdinSource {
+			stdin: std::io::stdin(),
+			format,
+			channels,
+		}
+	}
+
+	fn read_sample(&mut self) -> std::io::Result<i16>
+	{
+		let f32_to_i16 = |s: f32| (s.clamp(-1.0, 1.0) * 32767.0) as i16;
+
+		let sample = match self.format {
+			SampleFormat::S16NE => self.stdin.read_i16::<NativeEndian>()?,
+			SampleFormat::S16LE => self.stdin.read_i16::<LittleEndian>()?,
+			SampleFormat::S16BE => self.stdin.read_i16::<BigEndian>()?,
+			SampleFormat::S32NE => (self.stdin.read_i32::<NativeEndian>()? >> 16) as i16,
+			SampleFormat::S32LE => (self.stdin.read_i32::<LittleEndian>()? >> 16) as i16,
+			SampleFormat::S32BE => (self.stdin.read_i32::<BigEndian>()? >> 16) as i16,
+			SampleFormat::F32NE => f32_to_i16(self.stdin.read_f32::<NativeEndian>()?),
+			SampleFormat::F32LE => f32_to_i16(self.stdin.read_f32::<LittleEndian>()?),
+			SampleFormat::F32BE => f32_to_i16(self.stdin.read_f32::<BigEndian>()?),
+			SampleFormat::U8    => ((self.stdin.read_u8()? as i16) - 128) << 8,
+		};
+
+		Ok(sample)
+	}
+}
+
+impl AudioSource for StdinSource
+{
+	fn read_frame(&mut self) -> std::io::Result<Option<Frame>>
+	{
+		let mut frame = [0; 2];
+
+		for sample in frame.iter_mut().take(self.channels) {
+			match self.read_sample() {
+				Ok(s) => *sample = s,
+				Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+				Err(e) => return Err(e),
+			}
+		}
+
+		if self.channels == 1 {
+			frame[1] = frame[0];
+		}
+
+		Ok(Some(frame))
+	}
+}
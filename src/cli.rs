@@ -0,0 +1,203 @@
+// vim: noet
+
+use std::fmt;
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::path::Path;
+use std::str::FromStr;
+
+use getopts::Options;
+
+use crate::animation;
+use crate::config::{Config, ConfigError, OutputProtocol, NetworkFormat, SampleFormat, StereoLayout, StereoMode, WindowFunction};
+
+type Result<T> = std::result::Result<T, CliError>;
+
+/////////// Error Type and Implementation ////////////
+
+#[derive(Debug)]
+pub enum CliError
+{
+	Getopts(getopts::Fail),
+	Config(ConfigError),
+	InvalidArgument(&'static str, std::string::String),
+	UnexpectedArgument(std::string::String),
+}
+
+impl fmt::Display for CliError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CliError::Getopts(e)                 => f.write_fmt(format_args!("{}", e))?,
+			CliError::Config(e)                  => f.write_fmt(format_args!("{}", e))?,
+			CliError::InvalidArgument(opt, arg)  => f.write_fmt(format_args!("Invalid argument for --{}: \"{}\"", opt, arg))?,
+			CliError::UnexpectedArgument(arg)    => f.write_fmt(format_args!("Unexpected argument: \"{}\"", arg))?,
+		};
+
+		Ok(())
+	}
+}
+
+impl StdError for CliError {
+	fn description(&self) -> &str {
+		match *self {
+			CliError::Getopts(_)            => "Command Line Error",
+			CliError::Config(_)             => "Configuration Error",
+			CliError::InvalidArgument(_, _) => "Invalid Argument",
+			CliError::UnexpectedArgument(_) => "Unexpected Argument",
+		}
+	}
+}
+
+impl From<getopts::Fail> for CliError {
+	fn from(e: getopts::Fail) -> CliError {
+		CliError::Getopts(e)
+	}
+}
+
+impl From<ConfigError> for CliError {
+	fn from(e: ConfigError) -> CliError {
+		CliError::Config(e)
+	}
+}
+
+/////////// Command Line Parsing ////////////
+
+pub enum Command
+{
+	/// Run the main loop with the given configuration.
+	Run(Box<Config>),
+
+	/// Everything has been done already (e.g. the help was printed).
+	Exit,
+}
+
+fn options() -> Options
+{
+	let mut opts = Options::new();
+
+	opts.optopt("c", "config", "load settings from a TOML configuration file", "FILE");
+	opts.optopt("a", "animation", "animation to run (see --list-animations)", "NAME");
+	opts.optopt("", "crossfade", "duration of the crossfade when switching animations", "SECONDS");
+	opts.optopt("", "switch-interval", "switch to the next animation periodically (0 = never)", "SECONDS");
+	opts.optopt("", "control", "listen for control commands on this UDP address", "HOST:PORT");
+	opts.optopt("o", "output", &format!("output protocol ({})", OutputProtocol::NAMES.join(", ")), "PROTOCOL");
+	opts.optopt("t", "target", "address of the LED controller", "HOST:PORT");
+	opts.optopt("s", "strips", "number of LED strips", "N");
+	opts.optopt("l", "leds", "number of LEDs per strip", "N");
+	opts.optopt("r", "sample-rate", "sample rate of the input in Hz (detected for files)", "RATE");
+	opts.optopt("", "resample", "resample the input to this rate before the analysis", "RATE");
+	opts.optopt("f", "input-format", &format!("format of the input samples ({})", SampleFormat::NAMES.join(", ")), "FORMAT");
+	opts.optopt("", "channels", "number of interleaved channels on stdin or the network (1 or 2)", "N");
+	opts.optopt("", "stereo", &format!("processing of stereo input ({})", StereoMode::NAMES.join(", ")), "MODE");
+	opts.optopt("", "stereo-layout", &format!("LEDs showing the left and right channel ({})", StereoLayout::NAMES.join(", ")), "LAYOUT");
+	opts.optopt("i", "input", "play a WAV or FLAC file instead of reading stdin", "FILE");
+	opts.optopt("", "listen", "receive the audio over UDP on this address instead of reading stdin", "HOST:PORT");
+	opts.optopt("", "network-format", &format!("packet format of the network input ({})", NetworkFormat::NAMES.join(", ")), "FORMAT");
+	opts.optopt("", "jitter-buffer", "audio buffered before playing the network input", "SECONDS");
+	opts.optopt("", "block-len", "number of samples per FFT block", "N");
+	opts.optopt("", "hop", "number of samples between two FFT updates", "N");
+	opts.optopt("", "window", &format!("window function of the FFT ({})", WindowFunction::NAMES.join(", ")), "NAME");
+	opts.optopt("", "bass-block-len", "number of samples of an additional, longer FFT for the bass range", "N");
+	opts.optflag("", "fast", "process the input file as fast as possible instead of in real time");
+	opts.optopt("", "fps", "frames per second sent to the LEDs", "FPS");
+	opts.optflag("", "debug", "print values of the signal analysis once per second");
+	opts.optflag("", "list-animations", "list the available animations and exit");
+	opts.optflag("h", "help", "print this help and exit");
+
+	opts
+}
+
+fn parse_value<T: FromStr>(matches: &getopts::Matches, opt: &'static str) -> Result<Option<T>>
+{
+	match matches.opt_str(opt) {
+		Some(s) => match s.parse::<T>() {
+			Ok(v)  => Ok(Some(v)),
+			Err(_) => Err(CliError::InvalidArgument(opt, s)),
+		},
+		None => Ok(None),
+	}
+}
+
+/// Parse the command line arguments (without the program name). Settings given on the command
+/// line override those from the configuration file.
+pub fn parse(program: &str, args: impl IntoIterator<Item=OsString>) -> Result<Command>
+{
+	let opts = options();
+	let matches = opts.parse(args)?;
+
+	if matches.opt_present("help") {
+		let brief = format!("Usage: {} [options]\n\nReads raw audio samples from stdin (or an audio file or the network) and sends an animation to an LED controller.", program);
+		print!("{}", opts.usage(&brief));
+		return Ok(Command::Exit);
+	}
+
+	if matches.opt_present("list-animations") {
+		for (name, description) in animation::AVAILABLE.iter() {
+			println!("{:12} {}", name, description);
+		}
+		return Ok(Command::Exit);
+	}
+
+	if let Some(arg) = matches.free.first() {
+		return Err(CliError::UnexpectedArgument(arg.clone()));
+	}
+
+	let mut config = match matches.opt_str("config") {
+		Some(path) => Config::load(Path::new(&path))?,
+		None       => Config::default(),
+	};
+
+	if let Some(v) = matches.opt_str("animation")              { config.animation = v; }
+	if let Some(v) = parse_value(&matches, "crossfade")?       { config.crossfade_duration = v; }
+	if let Some(v) = parse_value(&matches, "switch-interval")? { config.switch_interval = v; }
+	if let Some(v) = matches.opt_str("control")                { config.control_addr = Some(v); }
+	if let Some(v) = matches.opt_str("target")                 { config.udp_server_addr = v; }
+	if let Some(v) = parse_value(&matches, "strips")?          { config.num_strips = v; }
+	if let Some(v) = parse_value(&matches, "leds")?            { config.num_leds_per_strip = v; }
+	if let Some(v) = parse_value(&matches, "sample-rate")?     { config.samp_rate = v; }
+	if let Some(v) = parse_value(&matches, "resample")?        { config.resample_rate = Some(v); }
+	if let Some(v) = parse_value(&matches, "fps")?             { config.fps_leds = v; }
+	if let Some(v) = parse_value(&matches, "channels")?        { config.input_channels = v; }
+	if let Some(v) = matches.opt_str("input")                  { config.input_file = Some(v); }
+	if let Some(v) = matches.opt_str("listen")                 { config.input_network = Some(v); }
+	if let Some(v) = parse_value(&matches, "jitter-buffer")?   { config.jitter_buffer = v; }
+	if let Some(v) = parse_value(&matches, "block-len")?       { config.block_len = v; }
+	if let Some(v) = parse_value(&matches, "hop")?             { config.hop_len = Some(v); }
+	if let Some(v) = parse_value(&matches, "bass-block-len")?  { config.bass_block_len = Some(v); }
+
+	if matches.opt_present("fast") {
+		config.input_realtime = false;
+	}
+
+	if matches.opt_present("debug") {
+		config.debug = true;
+	}
+
+	if let Some(v) = matches.opt_str("input-format") {
+		config.input_format = v.parse()?;
+	}
+
+	if let Some(v) = matches.opt_str("network-format") {
+		config.input_network_format = v.parse()?;
+	}
+
+	if let Some(v) = matches.opt_str("window") {
+		config.window = v.parse()?;
+	}
+
+	if let Some(v) = matches.opt_str("stereo") {
+		config.stereo_mode = v.parse()?;
+	}
+
+	if let Some(v) = matches.opt_str("stereo-layout") {
+		config.stereo_layout = v.parse()?;
+	}
+
+	if let Some(v) = matches.opt_str("output") {
+		config.output = v.parse()?;
+	}
+
+	config.validate()?;
+
+	Ok(Command::Run(Box::new(config)))
+}
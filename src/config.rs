@@ -1,21 +1,1281 @@
-// definitions for the FFT
-pub const BLOCK_LEN: usize = 512;
-pub const SAMP_RATE: f32   = 48000.0;
+// vim: noet
 
-// samples read from stdin per update
-pub const SAMPLES_PER_UPDATE: usize = BLOCK_LEN/2;
+use std::fmt;
+use std::error::Error as StdError;
+use std::path::Path;
+use std::str::FromStr;
 
-// LED configuration
-pub const NUM_STRIPS:         usize =  1;
-pub const NUM_LEDS_PER_STRIP: usize = 322;
+use serde::Deserialize;
 
-pub const NUM_LEDS_TOTAL: usize = NUM_STRIPS * NUM_LEDS_PER_STRIP;
+use crate::animation::{self, Parameters};
+use crate::signal_processing::tempo;
 
-// network configuration
-pub const UDP_SERVER_ADDR: &str = "wled1:21324";
+type Result<T> = std::result::Result<T, ConfigError>;
 
-pub const FPS_ANIMATION: f32 = SAMP_RATE / SAMPLES_PER_UPDATE as f32;
-pub const FPS_LEDS: f32 = 30.0;
+/////////// Error Type and Implementation ////////////
 
-// “standby mode” configuration
-pub const STANDBY_MAX_SILENT_SAMPLES: usize = SAMP_RATE as usize;
+#[derive(Debug)]
+pub enum ConfigError
+{
+	Io(std::io::Error),
+	Parse(toml::de::Error),
+	InvalidValue(&'static str, std::string::String),
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Io(e)                  => f.write_fmt(format_args!("Cannot read configuration file: {}", e))?,
+			ConfigError::Parse(e)               => f.write_fmt(format_args!("Cannot parse configuration file: {}", e))?,
+			ConfigError::InvalidValue(key, msg) => f.write_fmt(format_args!("Invalid value for '{}': {}", key, msg))?,
+		};
+
+		Ok(())
+	}
+}
+
+impl StdError for ConfigError {
+	fn description(&self) -> &str {
+		match *self {
+			ConfigError::Io(_)              => "I/O Error",
+			ConfigError::Parse(_)           => "Parse Error",
+			ConfigError::InvalidValue(_, _) => "Invalid Value",
+		}
+	}
+}
+
+impl From<std::io::Error> for ConfigError {
+	fn from(e: std::io::Error) -> ConfigError {
+		ConfigError::Io(e)
+	}
+}
+
+impl From<toml::de::Error> for ConfigError {
+	fn from(e: toml::de::Error) -> ConfigError {
+		ConfigError::Parse(e)
+	}
+}
+
+/////////// Configuration ////////////
+
+/// Format of the raw samples read from the input.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum SampleFormat
+{
+	#[serde(rename = "s16ne")]
+	S16NE,
+	#[serde(rename = "s16le")]
+	S16LE,
+	#[serde(rename = "s16be")]
+	S16BE,
+	#[serde(rename = "s32ne")]
+	S32NE,
+	#[serde(rename = "s32le")]
+	S32LE,
+	#[serde(rename = "s32be")]
+	S32BE,
+	#[serde(rename = "f32ne")]
+	F32NE,
+	#[serde(rename = "f32le")]
+	F32LE,
+	#[serde(rename = "f32be")]
+	F32BE,
+	#[serde(rename = "u8")]
+	U8,
+}
+
+impl SampleFormat
+{
+	pub const NAMES: [&'static str; 10] = ["s16ne", "s16le", "s16be", "s32ne", "s32le", "s32be", "f32ne", "f32le", "f32be", "u8"];
+}
+
+impl FromStr for SampleFormat
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<SampleFormat>
+	{
+		match s {
+			"s16ne" => Ok(SampleFormat::S16NE),
+			"s16le" => Ok(SampleFormat::S16LE),
+			"s16be" => Ok(SampleFormat::S16BE),
+			"s32ne" => Ok(SampleFormat::S32NE),
+			"s32le" => Ok(SampleFormat::S32LE),
+			"s32be" => Ok(SampleFormat::S32BE),
+			"f32ne" => Ok(SampleFormat::F32NE),
+			"f32le" => Ok(SampleFormat::F32LE),
+			"f32be" => Ok(SampleFormat::F32BE),
+			"u8"    => Ok(SampleFormat::U8),
+			_ => Err(ConfigError::InvalidValue("input_format",
+				format!("must be one of {}, got \"{}\"", SampleFormat::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// How the channels of a stereo input are processed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StereoMode
+{
+	/// Mix both channels down to mono.
+	#[default]
+	Mix,
+	/// Additionally analyze the left and the right channel separately.
+	Separate,
+}
+
+impl StereoMode
+{
+	pub const NAMES: [&'static str; 2] = ["mix", "separate"];
+}
+
+impl FromStr for StereoMode
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<StereoMode>
+	{
+		match s {
+			"mix"      => Ok(StereoMode::Mix),
+			"separate" => Ok(StereoMode::Separate),
+			_ => Err(ConfigError::InvalidValue("stereo_mode",
+				format!("must be one of {}, got \"{}\"", StereoMode::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// Which LEDs show the left and which the right channel when the channels are analyzed separately.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StereoLayout
+{
+	/// The first half of each strip shows the left channel, the second half the right channel.
+	#[default]
+	Halves,
+	/// The first half of the strips shows the left channel, the other strips the right channel.
+	/// With a single strip, this is the same as `Halves`.
+	Strips,
+}
+
+impl StereoLayout
+{
+	pub const NAMES: [&'static str; 2] = ["halves", "strips"];
+}
+
+impl FromStr for StereoLayout
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<StereoLayout>
+	{
+		match s {
+			"halves" => Ok(StereoLayout::Halves),
+			"strips" => Ok(StereoLayout::Strips),
+			_ => Err(ConfigError::InvalidValue("stereo_layout",
+				format!("must be one of {}, got \"{}\"", StereoLayout::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// Packet format of the network audio input.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkFormat
+{
+	/// RTP with an L16 payload (big-endian 16 bit samples), as sent by PulseAudio's module-rtp-send.
+	#[default]
+	Rtp,
+	/// A 32 bit little-endian sequence number followed by interleaved s16le samples.
+	Framed,
+}
+
+impl NetworkFormat
+{
+	pub const NAMES: [&'static str; 2] = ["rtp", "framed"];
+}
+
+impl FromStr for NetworkFormat
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<NetworkFormat>
+	{
+		match s {
+			"rtp"    => Ok(NetworkFormat::Rtp),
+			"framed" => Ok(NetworkFormat::Framed),
+			_ => Err(ConfigError::InvalidValue("input_network_format",
+				format!("must be one of {}, got \"{}\"", NetworkFormat::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// Level measurement used for the silence detection.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LevelDetector
+{
+	#[default]
+	Rms,
+	Peak,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SilenceConfig
+{
+	pub detector: LevelDetector,
+
+	// levels in dBFS: the input becomes quiet below enter_threshold and loud again above
+	// exit_threshold
+	pub enter_threshold: f32,
+	pub exit_threshold:  f32,
+
+	// seconds the input must stay quiet until it counts as silent, and loud until it no longer does
+	pub enter_hold: f32,
+	pub exit_hold:  f32,
+}
+
+impl Default for SilenceConfig
+{
+	fn default() -> SilenceConfig
+	{
+		SilenceConfig {
+			detector: LevelDetector::Rms,
+
+			enter_threshold: -70.0,
+			exit_threshold:  -60.0,
+
+			enter_hold: 1.0,
+			exit_hold:  0.1,
+		}
+	}
+}
+
+/// Window function applied to each block before the FFT.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowFunction
+{
+	#[default]
+	Hann,
+	Hamming,
+	BlackmanHarris,
+	FlatTop,
+	/// Kaiser window with the shape parameter `kaiser_beta`.
+	Kaiser,
+}
+
+impl WindowFunction
+{
+	pub const NAMES: [&'static str; 5] = ["hann", "hamming", "blackman-harris", "flat-top", "kaiser"];
+}
+
+impl FromStr for WindowFunction
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<WindowFunction>
+	{
+		match s {
+			"hann"            => Ok(WindowFunction::Hann),
+			"hamming"         => Ok(WindowFunction::Hamming),
+			"blackman-harris" => Ok(WindowFunction::BlackmanHarris),
+			"flat-top"        => Ok(WindowFunction::FlatTop),
+			"kaiser"          => Ok(WindowFunction::Kaiser),
+			_ => Err(ConfigError::InvalidValue("window",
+				format!("must be one of {}, got \"{}\"", WindowFunction::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// Frequency scale on which the bands of the filterbank are evenly spaced.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrequencyScale
+{
+	#[default]
+	Log,
+	Mel,
+	Bark,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterbankConfig
+{
+	pub scale: FrequencyScale,
+	pub bands: usize,
+
+	// frequency range in Hz, limited to the Nyquist frequency
+	pub min_freq: f32,
+	pub max_freq: f32,
+}
+
+impl Default for FilterbankConfig
+{
+	fn default() -> FilterbankConfig
+	{
+		FilterbankConfig {
+			scale: FrequencyScale::Log,
+			bands: 64,
+
+			min_freq:    30.0,
+			max_freq: 16000.0,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgcConfig
+{
+	// time constants in seconds for following a rising and a falling reference level
+	pub attack:  f32,
+	pub release: f32,
+
+	// fraction of the recent levels below the reference level, which is normalized to 1
+	pub percentile: f32,
+
+	// levels in dB relative to a full scale sine: nothing below floor is shown, and the reference
+	// level never rises above ceiling
+	pub floor:   f32,
+	pub ceiling: f32,
+}
+
+impl Default for AgcConfig
+{
+	fn default() -> AgcConfig
+	{
+		AgcConfig {
+			attack:  0.05,
+			release: 10.0,
+
+			percentile: 0.95,
+
+			floor:   -80.0,
+			ceiling:   0.0,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistOrder
+{
+	#[default]
+	Sequential,
+	Shuffle,
+}
+
+/// One entry of the playlist. The playlist advances to the next entry as soon as any of the
+/// given conditions is met.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaylistEntry
+{
+	pub animation: String,
+
+	// wall-clock time in seconds
+	pub duration: Option<f32>,
+
+	// number of detected beats
+	pub beats: Option<u32>,
+
+	// length of a silence gap in seconds
+	pub silence: Option<f32>,
+
+	#[serde(default)]
+	pub params: Parameters,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaylistConfig
+{
+	#[serde(default)]
+	pub order: PlaylistOrder,
+
+	pub entries: Vec<PlaylistEntry>,
+}
+
+/// How a layer is combined with the layers below it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlendMode
+{
+	Add,
+	Max,
+	Multiply,
+	Screen,
+	#[default]
+	AlphaOver,
+}
+
+fn default_opacity() -> f32
+{
+	1.0
+}
+
+/// One layer of the "layers" animation. Layers are composited from bottom (first) to top (last).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerConfig
+{
+	pub animation: String,
+
+	#[serde(default)]
+	pub blend: BlendMode,
+
+	#[serde(default = "default_opacity")]
+	pub opacity: f32,
+
+	// only draw on this range of LEDs (counted over all strips) and/or strips, end exclusive
+	pub leds: Option<(usize, usize)>,
+	pub strips: Option<(usize, usize)>,
+
+	#[serde(default)]
+	pub params: Parameters,
+}
+
+/// Protocol used to send the LED colors.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputProtocol
+{
+	#[default]
+	Wled,
+	E131,
+	ArtNet,
+	Ddp,
+	Opc,
+	Tpm2Net,
+	Tpm2,
+	Adalight,
+}
+
+impl OutputProtocol
+{
+	pub const NAMES: [&'static str; 8] = ["wled", "e131", "artnet", "ddp", "opc", "tpm2net", "tpm2", "adalight"];
+}
+
+impl FromStr for OutputProtocol
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<OutputProtocol>
+	{
+		match s {
+			"wled"     => Ok(OutputProtocol::Wled),
+			"e131"     => Ok(OutputProtocol::E131),
+			"artnet"   => Ok(OutputProtocol::ArtNet),
+			"ddp"      => Ok(OutputProtocol::Ddp),
+			"opc"      => Ok(OutputProtocol::Opc),
+			"tpm2net"  => Ok(OutputProtocol::Tpm2Net),
+			"tpm2"     => Ok(OutputProtocol::Tpm2),
+			"adalight" => Ok(OutputProtocol::Adalight),
+			_ => Err(ConfigError::InvalidValue("output",
+				format!("must be one of {}, got \"{}\"", OutputProtocol::NAMES.join(", "), s))),
+		}
+	}
+}
+
+/// Channel layout of one pixel on the wire.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat
+{
+	#[default]
+	Rgb,
+	Rgbw,
+}
+
+impl PixelFormat
+{
+	pub fn bytes_per_pixel(&self) -> usize
+	{
+		match self {
+			PixelFormat::Rgb  => 3,
+			PixelFormat::Rgbw => 4,
+		}
+	}
+}
+
+/// How the white channel is handled when sending to RGB-only devices.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhiteConversion
+{
+	/// Add white to all color channels, clipping at full brightness.
+	#[default]
+	Add,
+	/// Add white to all color channels and scale the result down if it would clip, which keeps
+	/// the hue.
+	Scale,
+	/// Ignore the white channel.
+	Drop,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WledConfig
+{
+	// rgbw uses DRGBW if all LEDs fit into one packet; otherwise white is added to the colors
+	pub pixel_format: PixelFormat,
+}
+
+impl Default for WledConfig
+{
+	fn default() -> WledConfig
+	{
+		WledConfig {
+			pixel_format: PixelFormat::Rgbw,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct E131Config
+{
+	// first universe and first channel (1-based) in it
+	pub universe:      u16,
+	pub start_channel: u16,
+
+	pub pixel_format: PixelFormat,
+	pub priority:     u8,
+	pub source_name:  String,
+
+	// send to the multicast address of each universe instead of udp_server_addr
+	pub multicast: bool,
+}
+
+impl Default for E131Config
+{
+	fn default() -> E131Config
+	{
+		E131Config {
+			universe:      1,
+			start_channel: 1,
+
+			pixel_format: PixelFormat::Rgb,
+			priority:     100,
+			source_name:  "musiclight".to_string(),
+
+			multicast: false,
+		}
+	}
+}
+
+impl E131Config
+{
+	/// Number of DMX universes needed for the given number of LEDs.
+	pub fn num_universes(&self, num_leds: usize) -> usize
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let pixels_in_first_universe = (512 - (self.start_channel as usize - 1)) / bytes_per_pixel;
+		let pixels_per_universe = 512 / bytes_per_pixel;
+
+		1 + num_leds.saturating_sub(pixels_in_first_universe).div_ceil(pixels_per_universe)
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArtNetConfig
+{
+	// port address of the first universe
+	pub net:      u8,
+	pub subnet:   u8,
+	pub universe: u8,
+
+	pub pixel_format: PixelFormat,
+
+	// allow sending to a broadcast address
+	pub broadcast: bool,
+
+	// send ArtSync after each frame so all universes are output at the same time
+	pub sync: bool,
+}
+
+impl Default for ArtNetConfig
+{
+	fn default() -> ArtNetConfig
+	{
+		ArtNetConfig {
+			net:      0,
+			subnet:   0,
+			universe: 0,
+
+			pixel_format: PixelFormat::Rgb,
+
+			broadcast: false,
+			sync:      true,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DdpConfig
+{
+	pub pixel_format: PixelFormat,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tpm2Config
+{
+	pub pixel_format: PixelFormat,
+
+	// serial device for the tpm2 output
+	pub device: String,
+
+	// longest serial frame in bytes of pixel data; longer frames are split
+	pub max_frame_len: usize,
+}
+
+impl Default for Tpm2Config
+{
+	fn default() -> Tpm2Config
+	{
+		Tpm2Config {
+			pixel_format: PixelFormat::Rgb,
+
+			device: "/dev/ttyACM0".to_string(),
+
+			max_frame_len: 65535,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdalightConfig
+{
+	pub device: String,
+	pub white:  WhiteConversion,
+}
+
+impl Default for AdalightConfig
+{
+	fn default() -> AdalightConfig
+	{
+		AdalightConfig {
+			device: "/dev/ttyUSB0".to_string(),
+			white:  WhiteConversion::Add,
+		}
+	}
+}
+
+/// Order of the color channels sent to a target.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelOrder
+{
+	/// RGB, with white added to the color channels.
+	#[default]
+	Rgb,
+	/// GRB, with white added to the color channels.
+	Grb,
+	/// RGB plus a separate white channel.
+	Rgbw,
+}
+
+/*
+ * One of several output targets. Each target receives a part of the frame and sends it with its
+ * own protocol; unset protocol sections are taken from the global configuration.
+ */
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig
+{
+	pub output: OutputProtocol,
+
+	// defaults to udp_server_addr
+	pub address: Option<String>,
+
+	// LEDs (counted over all strips) or strips sent to this target, end exclusive; all if unset
+	pub leds: Option<(usize, usize)>,
+	pub strips: Option<(usize, usize)>,
+
+	#[serde(default)]
+	pub pixel_order: PixelOrder,
+
+	// send the range in reverse order, starting at LED `offset` of the target
+	#[serde(default)]
+	pub reverse: bool,
+	#[serde(default)]
+	pub offset: usize,
+
+	pub wled:     Option<WledConfig>,
+	pub e131:     Option<E131Config>,
+	pub artnet:   Option<ArtNetConfig>,
+	pub ddp:      Option<DdpConfig>,
+	pub tpm2:     Option<Tpm2Config>,
+	pub adalight: Option<AdalightConfig>,
+}
+
+impl TargetConfig
+{
+	/// Range of global LED indices sent to this target.
+	pub fn led_range(&self, config: &Config) -> std::ops::Range<usize>
+	{
+		match (self.leds, self.strips) {
+			(Some((start, end)), _) => start..end,
+			(None, Some((start, end))) => start * config.num_leds_per_strip .. end * config.num_leds_per_strip,
+			(None, None) => 0..config.num_leds_total(),
+		}
+	}
+
+	/// Build the configuration for the output of this target: a single strip with its LEDs and
+	/// the protocol settings of the target instead of the global ones.
+	pub fn output_config(&self, config: &Config) -> Config
+	{
+		let mut c = config.clone();
+
+		c.targets.clear();
+		c.output = self.output;
+
+		c.num_strips = 1;
+		c.num_leds_per_strip = self.offset + self.led_range(config).len();
+
+		if let Some(address) = &self.address {
+			c.udp_server_addr = address.clone();
+		}
+
+		if let Some(wled) = &self.wled         { c.wled = wled.clone(); }
+		if let Some(e131) = &self.e131         { c.e131 = e131.clone(); }
+		if let Some(artnet) = &self.artnet     { c.artnet = artnet.clone(); }
+		if let Some(ddp) = &self.ddp           { c.ddp = ddp.clone(); }
+		if let Some(tpm2) = &self.tpm2         { c.tpm2 = tpm2.clone(); }
+		if let Some(adalight) = &self.adalight { c.adalight = adalight.clone(); }
+
+		c
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config
+{
+	// animation to run
+	pub animation: String,
+
+	// animation switching
+	pub crossfade_duration: f32,
+	pub switch_interval:    f32,
+	pub control_addr:       Option<String>,
+
+	pub playlist: Option<PlaylistConfig>,
+
+	pub layers: Vec<LayerConfig>,
+
+	// input configuration
+	pub input_format:   SampleFormat,
+	pub input_channels: usize,
+	pub stereo_mode:    StereoMode,
+	pub stereo_layout:  StereoLayout,
+
+	// play a WAV or FLAC file instead of reading stdin, either in real time or as fast as possible
+	pub input_file:     Option<String>,
+	pub input_realtime: bool,
+
+	// receive the input over UDP on this address instead of reading stdin
+	pub input_network:        Option<String>,
+	pub input_network_format: NetworkFormat,
+	pub jitter_buffer:        f32,
+
+	// definitions for the FFT
+	pub block_len: usize,
+	pub samp_rate: f32,
+
+	// samples between two FFT updates (default: half the block length) and the window function
+	pub hop_len:     Option<usize>,
+	pub window:      WindowFunction,
+	pub kaiser_beta: f32,
+
+	// multi-resolution analysis: a longer FFT for the frequencies below bass_crossover (Hz)
+	pub bass_block_len: Option<usize>,
+	pub bass_crossover: f32,
+
+	// resample the input to this rate before the analysis
+	pub resample_rate: Option<f32>,
+
+	// LED configuration
+	pub num_strips:         usize,
+	pub num_leds_per_strip: usize,
+
+	// output configuration
+	pub output: OutputProtocol,
+	pub udp_server_addr: String,
+
+	pub wled: WledConfig,
+	pub e131: E131Config,
+	pub artnet: ArtNetConfig,
+	pub ddp: DdpConfig,
+	pub tpm2: Tpm2Config,
+	pub adalight: AdalightConfig,
+
+	// send to several targets instead of a single output
+	pub targets: Vec<TargetConfig>,
+
+	pub fps_leds: f32,
+
+	// print values of the signal analysis once per second
+	pub debug: bool,
+
+	// silence detection, which also controls the “standby mode”
+	pub silence: SilenceConfig,
+
+	// bands of the filterbank analysis
+	pub filterbank: FilterbankConfig,
+
+	pub agc: AgcConfig,
+}
+
+impl Default for Config
+{
+	fn default() -> Config
+	{
+		Config {
+			animation: "racers".to_string(),
+
+			crossfade_duration: 2.0,
+			switch_interval:    0.0,
+			control_addr:       None,
+
+			playlist: None,
+
+			layers: Vec::new(),
+
+			input_format:   SampleFormat::S16NE,
+			input_channels: 1,
+			stereo_mode:    StereoMode::Mix,
+			stereo_layout:  StereoLayout::Halves,
+
+			input_file:     None,
+			input_realtime: true,
+
+			input_network:        None,
+			input_network_format: NetworkFormat::Rtp,
+			jitter_buffer:        0.06,
+
+			block_len: 512,
+			samp_rate: 48000.0,
+
+			hop_len:     None,
+			window:      WindowFunction::Hann,
+			kaiser_beta: 8.6,
+
+			bass_block_len: None,
+			bass_crossover: 250.0,
+
+			resample_rate: None,
+
+			num_strips:           1,
+			num_leds_per_strip: 322,
+
+			output: OutputProtocol::Wled,
+			udp_server_addr: "wled1:21324".to_string(),
+
+			wled: WledConfig::default(),
+			e131: E131Config::default(),
+			artnet: ArtNetConfig::default(),
+			ddp: DdpConfig::default(),
+			tpm2: Tpm2Config::default(),
+			adalight: AdalightConfig::default(),
+
+			targets: Vec::new(),
+
+			fps_leds: 30.0,
+
+			debug: false,
+
+			silence: SilenceConfig::default(),
+
+			filterbank: FilterbankConfig::default(),
+
+			agc: AgcConfig::default(),
+		}
+	}
+}
+
+/// Check whether the given address has the form "host:port".
+fn is_host_port(addr: &str) -> bool
+{
+	match addr.rsplit_once(':') {
+		Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+		None               => false,
+	}
+}
+
+impl Config
+{
+	/// Load the configuration from a TOML file. Settings missing in the file keep their default
+	/// values.
+	pub fn load(path: &Path) -> Result<Config>
+	{
+		let content = std::fs::read_to_string(path)?;
+		let config: Config = toml::from_str(&content)?;
+
+		config.validate()?;
+
+		Ok(config)
+	}
+
+	pub fn validate(&self) -> Result<()>
+	{
+		if !animation::names().contains(&self.animation.as_str()) {
+			return Err(ConfigError::InvalidValue("animation",
+				format!("must be one of {}, got \"{}\"", animation::names().join(", "), self.animation)));
+		}
+
+		if self.input_channels < 1 || self.input_channels > 2 {
+			return Err(ConfigError::InvalidValue("input_channels",
+				format!("must be 1 or 2, got {}", self.input_channels)));
+		}
+
+		if self.input_file.is_some() && self.input_network.is_some() {
+			return Err(ConfigError::InvalidValue("input_network",
+				"cannot be combined with input_file".to_string()));
+		}
+
+		if self.input_network.as_deref().is_some_and(|a| !is_host_port(a)) {
+			return Err(ConfigError::InvalidValue("input_network",
+				format!("must have the form \"host:port\", got \"{}\"", self.input_network.as_ref().unwrap())));
+		}
+
+		if !self.jitter_buffer.is_finite() || self.jitter_buffer < 0.0 {
+			return Err(ConfigError::InvalidValue("jitter_buffer",
+				format!("must be a non-negative number, got {}", self.jitter_buffer)));
+		}
+
+		if self.block_len < 2 || !self.block_len.is_multiple_of(2) {
+			return Err(ConfigError::InvalidValue("block_len",
+				format!("must be an even number of at least 2, got {}", self.block_len)));
+		}
+
+		if self.hop_len.is_some_and(|h| h < 1 || h > self.block_len) {
+			return Err(ConfigError::InvalidValue("hop_len",
+				format!("must be between 1 and block_len, got {}", self.hop_len.unwrap())));
+		}
+
+		if !self.kaiser_beta.is_finite() || self.kaiser_beta < 0.0 {
+			return Err(ConfigError::InvalidValue("kaiser_beta",
+				format!("must be a non-negative number, got {}", self.kaiser_beta)));
+		}
+
+		if self.bass_block_len.is_some_and(|b| b <= self.block_len || !b.is_multiple_of(2)) {
+			return Err(ConfigError::InvalidValue("bass_block_len",
+				format!("must be an even number greater than block_len, got {}", self.bass_block_len.unwrap())));
+		}
+
+		if !self.bass_crossover.is_finite() || self.bass_crossover <= 0.0 {
+			return Err(ConfigError::InvalidValue("bass_crossover",
+				format!("must be a positive number, got {}", self.bass_crossover)));
+		}
+
+		if !self.samp_rate.is_finite() || self.samp_rate <= 0.0 {
+			return Err(ConfigError::InvalidValue("samp_rate",
+				format!("must be a positive number, got {}", self.samp_rate)));
+		}
+
+		// the tempo tracking needs at least two updates per beat at the fastest tempo
+		let max_hop = (tempo::MAX_UPDATE_PERIOD * self.samp_rate) as usize;
+
+		if self.samples_per_update() > max_hop {
+			return Err(ConfigError::InvalidValue("hop_len",
+				format!("must be at most {} samples at this sample rate (default: block_len / 2), got {}", max_hop, self.samples_per_update())));
+		}
+
+		if self.resample_rate.is_some_and(|r| !r.is_finite() || r <= 0.0) {
+			return Err(ConfigError::InvalidValue("resample_rate",
+				format!("must be a positive number, got {}", self.resample_rate.unwrap())));
+		}
+
+		let silence = &self.silence;
+
+		if !silence.enter_threshold.is_finite() || silence.enter_threshold > 0.0 {
+			return Err(ConfigError::InvalidValue("silence.enter_threshold",
+				format!("must be a level of at most 0 dBFS, got {}", silence.enter_threshold)));
+		}
+
+		if !silence.exit_threshold.is_finite() || silence.exit_threshold < silence.enter_threshold || silence.exit_threshold > 0.0 {
+			return Err(ConfigError::InvalidValue("silence.exit_threshold",
+				format!("must be between enter_threshold and 0 dBFS, got {}", silence.exit_threshold)));
+		}
+
+		if !silence.enter_hold.is_finite() || silence.enter_hold < 0.0 {
+			return Err(ConfigError::InvalidValue("silence.enter_hold",
+				format!("must be a non-negative number, got {}", silence.enter_hold)));
+		}
+
+		if !silence.exit_hold.is_finite() || silence.exit_hold < 0.0 {
+			return Err(ConfigError::InvalidValue("silence.exit_hold",
+				format!("must be a non-negative number, got {}", silence.exit_hold)));
+		}
+
+		if self.filterbank.bands < 1 {
+			return Err(ConfigError::InvalidValue("filterbank.bands",
+				format!("must be at least 1, got {}", self.filterbank.bands)));
+		}
+
+		if !self.filterbank.min_freq.is_finite() || self.filterbank.min_freq <= 0.0 {
+			return Err(ConfigError::InvalidValue("filterbank.min_freq",
+				format!("must be a positive number, got {}", self.filterbank.min_freq)));
+		}
+
+		if !self.filterbank.max_freq.is_finite() || self.filterbank.max_freq <= self.filterbank.min_freq {
+			return Err(ConfigError::InvalidValue("filterbank.max_freq",
+				format!("must be greater than min_freq, got {}", self.filterbank.max_freq)));
+		}
+
+		let agc = &self.agc;
+
+		if !agc.attack.is_finite() || agc.attack < 0.0 {
+			return Err(ConfigError::InvalidValue("agc.attack",
+				format!("must be a non-negative number, got {}", agc.attack)));
+		}
+
+		if !agc.release.is_finite() || agc.release < 0.0 {
+			return Err(ConfigError::InvalidValue("agc.release",
+				format!("must be a non-negative number, got {}", agc.release)));
+		}
+
+		if !agc.percentile.is_finite() || agc.percentile <= 0.0 || agc.percentile > 1.0 {
+			return Err(ConfigError::InvalidValue("agc.percentile",
+				format!("must be greater than 0.0 and at most 1.0, got {}", agc.percentile)));
+		}
+
+		if !agc.floor.is_finite() {
+			return Err(ConfigError::InvalidValue("agc.floor",
+				format!("must be a number, got {}", agc.floor)));
+		}
+
+		if !agc.ceiling.is_finite() || agc.ceiling <= agc.floor {
+			return Err(ConfigError::InvalidValue("agc.ceiling",
+				format!("must be greater than floor, got {}", agc.ceiling)));
+		}
+
+		// the strip index is transferred as an u8
+		if self.num_strips < 1 || self.num_strips > 256 {
+			return Err(ConfigError::InvalidValue("num_strips",
+				format!("must be between 1 and 256, got {}", self.num_strips)));
+		}
+
+		if self.num_leds_per_strip < 1 {
+			return Err(ConfigError::InvalidValue("num_leds_per_strip",
+				format!("must be at least 1, got {}", self.num_leds_per_strip)));
+		}
+
+		let uses_layers = self.animation == "layers"
+			|| self.playlist.as_ref().is_some_and(|p| p.entries.iter().any(|e| e.animation == "layers"));
+
+		if uses_layers && self.layers.is_empty() {
+			return Err(ConfigError::InvalidValue("layers",
+				"must contain at least one layer when the \"layers\" animation is used".to_string()));
+		}
+
+		for (i, layer) in self.layers.iter().enumerate() {
+			if layer.animation == "layers" || !animation::names().contains(&layer.animation.as_str()) {
+				return Err(ConfigError::InvalidValue("layers.animation",
+					format!("layer {}: must be one of {}, except layers, got \"{}\"", i + 1, animation::names().join(", "), layer.animation)));
+			}
+
+			if !layer.opacity.is_finite() || layer.opacity < 0.0 || layer.opacity > 1.0 {
+				return Err(ConfigError::InvalidValue("layers.opacity",
+					format!("layer {}: must be between 0.0 and 1.0, got {}", i + 1, layer.opacity)));
+			}
+
+			if let Some((start, end)) = layer.leds {
+				if start >= end || end > self.num_leds_total() {
+					return Err(ConfigError::InvalidValue("layers.leds",
+						format!("layer {}: must be a non-empty range within 0..{}, got {}..{}", i + 1, self.num_leds_total(), start, end)));
+				}
+			}
+
+			if let Some((start, end)) = layer.strips {
+				if start >= end || end > self.num_strips {
+					return Err(ConfigError::InvalidValue("layers.strips",
+						format!("layer {}: must be a non-empty range within 0..{}, got {}..{}", i + 1, self.num_strips, start, end)));
+				}
+			}
+		}
+
+		if let Some(playlist) = &self.playlist {
+			if playlist.entries.is_empty() {
+				return Err(ConfigError::InvalidValue("playlist.entries",
+					"must contain at least one entry".to_string()));
+			}
+
+			for (i, entry) in playlist.entries.iter().enumerate() {
+				if !animation::names().contains(&entry.animation.as_str()) {
+					return Err(ConfigError::InvalidValue("playlist.entries.animation",
+						format!("entry {}: must be one of {}, got \"{}\"", i + 1, animation::names().join(", "), entry.animation)));
+				}
+
+				if entry.duration.is_none() && entry.beats.is_none() && entry.silence.is_none() {
+					return Err(ConfigError::InvalidValue("playlist.entries",
+						format!("entry {}: at least one of duration, beats or silence must be given", i + 1)));
+				}
+
+				if entry.duration.is_some_and(|d| !d.is_finite() || d <= 0.0) {
+					return Err(ConfigError::InvalidValue("playlist.entries.duration",
+						format!("entry {}: must be a positive number", i + 1)));
+				}
+
+				if entry.beats == Some(0) {
+					return Err(ConfigError::InvalidValue("playlist.entries.beats",
+						format!("entry {}: must be at least 1", i + 1)));
+				}
+
+				if entry.silence.is_some_and(|s| !s.is_finite() || s <= 0.0) {
+					return Err(ConfigError::InvalidValue("playlist.entries.silence",
+						format!("entry {}: must be a positive number", i + 1)));
+				}
+			}
+		}
+
+		if !self.crossfade_duration.is_finite() || self.crossfade_duration < 0.0 {
+			return Err(ConfigError::InvalidValue("crossfade_duration",
+				format!("must be a non-negative number, got {}", self.crossfade_duration)));
+		}
+
+		if !self.switch_interval.is_finite() || self.switch_interval < 0.0 {
+			return Err(ConfigError::InvalidValue("switch_interval",
+				format!("must be a non-negative number, got {}", self.switch_interval)));
+		}
+
+		if let Some(addr) = &self.control_addr {
+			if !is_host_port(addr) {
+				return Err(ConfigError::InvalidValue("control_addr",
+					format!("must have the form \"host:port\", got \"{}\"", addr)));
+			}
+		}
+
+		if !is_host_port(&self.udp_server_addr) {
+			return Err(ConfigError::InvalidValue("udp_server_addr",
+				format!("must have the form \"host:port\", got \"{}\"", self.udp_server_addr)));
+		}
+
+		self.validate_output()?;
+
+		for (i, target) in self.targets.iter().enumerate() {
+			if target.leds.is_some() && target.strips.is_some() {
+				return Err(ConfigError::InvalidValue("targets",
+					format!("target {}: only one of leds and strips may be given", i + 1)));
+			}
+
+			if let Some((start, end)) = target.leds {
+				if start >= end || end > self.num_leds_total() {
+					return Err(ConfigError::InvalidValue("targets.leds",
+						format!("target {}: must be a non-empty range within 0..{}, got {}..{}", i + 1, self.num_leds_total(), start, end)));
+				}
+			}
+
+			if let Some((start, end)) = target.strips {
+				if start >= end || end > self.num_strips {
+					return Err(ConfigError::InvalidValue("targets.strips",
+						format!("target {}: must be a non-empty range within 0..{}, got {}..{}", i + 1, self.num_strips, start, end)));
+				}
+			}
+
+			if let Some(addr) = &target.address {
+				if !is_host_port(addr) {
+					return Err(ConfigError::InvalidValue("targets.address",
+						format!("target {}: must have the form \"host:port\", got \"{}\"", i + 1, addr)));
+				}
+			}
+
+			target.output_config(self).validate_output().map_err(|e| match e {
+				ConfigError::InvalidValue(key, msg) => ConfigError::InvalidValue(key, format!("target {}: {}", i + 1, msg)),
+				e                                   => e,
+			})?;
+		}
+
+		if !self.fps_leds.is_finite() || self.fps_leds <= 0.0 {
+			return Err(ConfigError::InvalidValue("fps_leds",
+				format!("must be a positive number, got {}", self.fps_leds)));
+		}
+
+		Ok(())
+	}
+
+	/// Check the settings of the output protocols. This is also done for the output of each
+	/// target.
+	fn validate_output(&self) -> Result<()>
+	{
+		if self.e131.universe < 1 || self.e131.universe > 63999 {
+			return Err(ConfigError::InvalidValue("e131.universe",
+				format!("must be between 1 and 63999, got {}", self.e131.universe)));
+		}
+
+		if self.e131.start_channel < 1 || self.e131.start_channel as usize + self.e131.pixel_format.bytes_per_pixel() - 1 > 512 {
+			return Err(ConfigError::InvalidValue("e131.start_channel",
+				format!("must leave room for at least one pixel in the universe, got {}", self.e131.start_channel)));
+		}
+
+		if self.e131.priority > 200 {
+			return Err(ConfigError::InvalidValue("e131.priority",
+				format!("must be between 0 and 200, got {}", self.e131.priority)));
+		}
+
+		if self.artnet.net > 127 {
+			return Err(ConfigError::InvalidValue("artnet.net",
+				format!("must be between 0 and 127, got {}", self.artnet.net)));
+		}
+
+		if self.artnet.subnet > 15 {
+			return Err(ConfigError::InvalidValue("artnet.subnet",
+				format!("must be between 0 and 15, got {}", self.artnet.subnet)));
+		}
+
+		if self.artnet.universe > 15 {
+			return Err(ConfigError::InvalidValue("artnet.universe",
+				format!("must be between 0 and 15, got {}", self.artnet.universe)));
+		}
+
+		if self.output == OutputProtocol::E131 {
+			let last_universe = self.e131.universe as usize + self.e131.num_universes(self.num_leds_total()) - 1;
+
+			if last_universe > 63999 {
+				return Err(ConfigError::InvalidValue("e131.universe",
+					format!("the {} LEDs need universes up to {}, but the maximum is 63999", self.num_leds_total(), last_universe)));
+			}
+		}
+
+		if self.tpm2.max_frame_len < self.tpm2.pixel_format.bytes_per_pixel() || self.tpm2.max_frame_len > 65535 {
+			return Err(ConfigError::InvalidValue("tpm2.max_frame_len",
+				format!("must fit at least one pixel and be at most 65535, got {}", self.tpm2.max_frame_len)));
+		}
+
+		if self.output == OutputProtocol::Adalight && self.num_leds_total() > 65536 {
+			return Err(ConfigError::InvalidValue("num_leds_per_strip",
+				format!("Adalight supports at most 65536 LEDs, got {}", self.num_leds_total())));
+		}
+
+		Ok(())
+	}
+
+	/// Samples read from the input per update.
+	pub fn samples_per_update(&self) -> usize
+	{
+		self.hop_len.unwrap_or(self.block_len / 2)
+	}
+
+	/// Number of most recent samples needed for one update, including the bass FFT.
+	pub fn analysis_len(&self) -> usize
+	{
+		self.bass_block_len.unwrap_or(0).max(self.block_len)
+	}
+
+	pub fn num_leds_total(&self) -> usize
+	{
+		self.num_strips * self.num_leds_per_strip
+	}
+
+	pub fn fps_animation(&self) -> f32
+	{
+		self.samp_rate / self.samples_per_update() as f32
+	}
+}
@@ -0,0 +1,69 @@
+// vim: noet
+
+use std::net::UdpSocket;
+
+const MAX_COMMAND_LEN: usize = 256;
+
+/// Commands that can be sent as plain text datagrams to the control socket.
+pub enum ControlCommand
+{
+	/// "next": switch to the next animation.
+	Next,
+
+	/// "animation <name>": switch to the named animation.
+	Animation(String),
+}
+
+impl ControlCommand
+{
+	fn parse(text: &str) -> Option<ControlCommand>
+	{
+		let mut words = text.split_whitespace();
+
+		match (words.next(), words.next(), words.next()) {
+			(Some("next"), None, None)            => Some(ControlCommand::Next),
+			(Some("animation"), Some(name), None) => Some(ControlCommand::Animation(name.to_string())),
+			_                                     => None,
+		}
+	}
+}
+
+pub struct ControlSocket
+{
+	socket: UdpSocket,
+}
+
+impl ControlSocket
+{
+	pub fn new(bind_address: &str) -> std::io::Result<ControlSocket>
+	{
+		let socket = UdpSocket::bind(bind_address)?;
+		socket.set_nonblocking(true)?;
+
+		Ok(ControlSocket {
+			socket,
+		})
+	}
+
+	/// Return the next pending command, if any. Never blocks. Invalid commands are reported and
+	/// skipped.
+	pub fn poll(&mut self) -> std::io::Result<Option<ControlCommand>>
+	{
+		let mut buf = [0u8; MAX_COMMAND_LEN];
+
+		loop {
+			let len = match self.socket.recv(&mut buf) {
+				Ok(len) => len,
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+				Err(e) => return Err(e),
+			};
+
+			let text = String::from_utf8_lossy(&buf[..len]);
+
+			match ControlCommand::parse(&text) {
+				Some(cmd) => return Ok(Some(cmd)),
+				None      => println!("Ignoring invalid control command: {:?}", text.trim()),
+			}
+		}
+	}
+}
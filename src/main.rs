@@ -3,32 +3,73 @@
 use std::process::exit;
 use std::collections::VecDeque;
 
-use byteorder::{NativeEndian, ReadBytesExt};
-
 mod signal_processing;
 mod config;
-mod udpproto;
+mod cli;
+mod control;
+mod output;
+mod audio;
 mod animation;
+mod playlist;
 
 use crate::signal_processing::SignalProcessing;
-use crate::udpproto::UdpProto;
-use crate::animation::Animation;
+use crate::animation::{AnimationError, Parameters};
+use crate::animation::switcher::Switcher;
+use crate::control::{ControlCommand, ControlSocket};
+use crate::playlist::Playlist;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 fn main()
 {
-	let mut stdin = std::io::stdin();
+	let mut args = std::env::args_os();
+	let program = args.next()
+	                  .map(|p| p.to_string_lossy().into_owned())
+	                  .unwrap_or_else(|| "musiclight".to_string());
+
+	let mut config = match cli::parse(&program, args) {
+		Ok(cli::Command::Run(c)) => *c,
+		Ok(cli::Command::Exit) => exit(0),
+		Err(e) => {
+			println!("{}\nTry '{} --help' for more information.", e, program);
+			exit(1);
+		}
+	};
+
+	// set up the audio input
+	let mut source = match audio::create(&config) {
+		Ok(s) => s,
+		Err(e) => {
+			println!("Error during input setup:\n{}", e);
+			exit(1);
+		}
+	};
 
-	// set up the UDP protocol
-	let mut udpproto = match UdpProto::new(config::UDP_SERVER_ADDR, config::NUM_LEDS_TOTAL) {
-		Ok(u) => u,
+	// files know their sample rate; after resampling, the analysis runs at the new rate
+	if let Some(rate) = source.sample_rate() {
+		config.samp_rate = rate;
+
+		// settings depending on the sample rate must still be valid
+		if let Err(e) = config.validate() {
+			println!("Error during input setup:\n{}", e);
+			exit(1);
+		}
+	}
+
+	// without pacing by the input, the main loop must not wait either
+	let run_fast = config.input_file.is_some() && !config.input_realtime;
+
+	// set up the output protocol
+	let mut output = match output::create(&config) {
+		Ok(o) => o,
 		Err(e) => {
-			println!("Error during UDP client setup:\n{}", e);
+			println!("Error during output setup:\n{}", e);
 			exit(1);
 		}
 	};
@@ -37,56 +78,92 @@ fn main()
 
 	println!("Initializing signal processing...");
 
-	let sigproc = Rc::new(RefCell::new(
-	                  SignalProcessing::new(config::BLOCK_LEN, config::SAMP_RATE).unwrap()));
+	let sigproc = Rc::new(RefCell::new(SignalProcessing::new(&config).unwrap()));
 
 	println!("Contructing Animation...");
 
-	// TODO: let the user select via the command line
-	//let mut anim: animation::particles::Particles = animation::Animation::new(sigproc.clone());
-	//let mut anim: animation::sparkles::Sparkles = animation::Animation::new(sigproc.clone());
-	let mut anim: animation::racers::Racers = animation::Animation::new(sigproc.clone());
-	//let mut anim: animation::spectrum::Spectrum = animation::Animation::new(sigproc.clone());
+	let mut playlist = config.playlist.as_ref().map(Playlist::new);
 
-	println!("Calling Animation::init()...");
+	// the playlist determines the initial animation
+	if let Some(p) = &playlist {
+		config.animation = p.current().animation.clone();
+	}
 
-	anim.init().unwrap();
+	let mut anim = match Switcher::new(sigproc.clone(), &config) {
+		Ok(a) => a,
+		Err(e) => {
+			println!("=== Animation Error ===\n{}\n====> Terminating.", e);
+			exit(1);
+		}
+	};
+
+	if let Some(p) = &playlist {
+		let entry = p.current();
+		report_switch(anim.switch_to(&entry.animation, &entry.params), &anim);
+	}
+
+	// set up the triggers for switching animations
+
+	let switch_requested = Arc::new(AtomicBool::new(false));
+
+	if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGUSR1, switch_requested.clone()) {
+		println!("Warning: cannot install SIGUSR1 handler: {}", e);
+	}
+
+	let mut control = match &config.control_addr {
+		Some(addr) => match ControlSocket::new(addr) {
+			Ok(c) => Some(c),
+			Err(e) => {
+				println!("Error during control socket setup:\n{}", e);
+				exit(1);
+			}
+		},
+		None => None,
+	};
+
+	let switch_period = match config.switch_interval {
+		i if i > 0.0 => Some(Duration::from_secs_f32(i)),
+		_            => None,
+	};
 
 	println!("Done! Starting main loop…");
 
 	// Timing setup
 
-	let block_period = Duration::from_nanos((0.95 * (config::SAMPLES_PER_UPDATE as f32) * 1e9 / config::SAMP_RATE) as u64);
-	let send_period = Duration::from_nanos((1000000000.0 / config::FPS_LEDS) as u64);
+	let update_period = config.samples_per_update() as f32 / config.samp_rate;
+
+	let block_period = Duration::from_nanos((0.95 * (config.samples_per_update() as f32) * 1e9 / config.samp_rate) as u64);
+	let send_period = Duration::from_nanos((1000000000.0 / config.fps_leds) as u64);
 
 	let max_lag = 5*send_period;
 
 	let mut next_block_instant = Instant::now() + block_period;
 	let mut next_send_instant = Instant::now() + send_period;
+	let mut next_switch_instant = switch_period.map(|p| Instant::now() + p);
 
 	// array for samples directly read from stream
-	let mut samples: VecDeque<i16> = VecDeque::with_capacity(config::BLOCK_LEN);
+	let mut samples: VecDeque<audio::Frame> = VecDeque::with_capacity(config.analysis_len());
 
-	// counts silent (zero-valued) samples
-	let mut silent_samples: usize = 0;
+	let mut in_standby = false;
+
+	// updates between two debug outputs
+	let debug_interval = ((1.0 / update_period).round() as usize).max(1);
+	let mut num_updates: usize = 0;
 
 	// main loop
 	loop {
 
 		// read a block of samples and exit gracefully on EOF
-		for _i in 0 .. config::SAMPLES_PER_UPDATE {
+		for _i in 0 .. config.samples_per_update() {
 			// avoid increasing the size of the deque
-			if samples.len() == config::BLOCK_LEN {
+			if samples.len() == config.analysis_len() {
 				samples.pop_front();
 			}
 
-			// read a sample from the input
-			let res = stdin.read_i16::<NativeEndian>();
-
-			// if everything is ok, append it to the samples deque
-			match res {
-				Ok(s) => samples.push_back(s),
-				Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+			// read a sample from the input and append it to the samples deque
+			match source.read_frame() {
+				Ok(Some(s)) => samples.push_back(s),
+				Ok(None) => {
 					println!("End of stream. Exiting.");
 					exit(0);
 				},
@@ -95,28 +172,91 @@ fn main()
 		}
 
 		// only run calculations if the deque has been filled enough
-		if samples.len() < config::BLOCK_LEN {
+		if samples.len() < config.analysis_len() {
 			continue;
 		}
 
 		// run the signal processing
-		{
+		let standby = {
 			let mut s = sigproc.borrow_mut();
-			s.import_i16_mono_from_iter(samples.iter()).unwrap();
+			s.import_i16_stereo_from_iter(samples.iter()).unwrap();
 
-			if s.is_silent() {
-				silent_samples += config::BLOCK_LEN;
+			let silent = s.is_silent();
 
-				if silent_samples >= config::STANDBY_MAX_SILENT_SAMPLES {
-					// too many silent samples in a row: stop any signal processing until something
-					// else occurs at the input again
-					continue;
-				}
+			if !silent {
+				s.update_fft().unwrap();
+			}
+
+			silent
+		};
+
+		if standby != in_standby {
+			let s = sigproc.borrow();
+
+			if standby {
+				println!("Input silent (RMS {:.1} dBFS, peak {:.1} dBFS). Entering standby.", s.get_rms_level(), s.get_peak_level());
 			} else {
-				silent_samples = 0;
+				println!("Input active again (RMS {:.1} dBFS, peak {:.1} dBFS). Leaving standby.", s.get_rms_level(), s.get_peak_level());
+			}
+
+			in_standby = standby;
+		}
+
+		// advance the playlist (this must also happen in standby to detect silence gaps)
+		if let Some(p) = playlist.as_mut() {
+			if let Some(entry) = p.update(&sigproc.borrow(), update_period) {
+				report_switch(anim.switch_to(&entry.animation, &entry.params), &anim);
+			}
+		}
+
+		// switch the animation if any trigger fired, also in standby so no request is delayed
+		let mut command: Option<ControlCommand> = None;
+
+		if switch_requested.swap(false, Ordering::Relaxed) {
+			command = Some(ControlCommand::Next);
+		}
+
+		if next_switch_instant.is_some_and(|t| Instant::now() > t) {
+			command = Some(ControlCommand::Next);
+		}
+
+		if let Some(c) = control.as_mut() {
+			match c.poll() {
+				Ok(Some(cmd)) => command = Some(cmd),
+				Ok(None) => (),
+				Err(e) => println!("Warning: cannot read from control socket: {}", e),
+			}
+		}
+
+		if let Some(cmd) = command {
+			let res = match (cmd, playlist.as_mut()) {
+				(ControlCommand::Next, Some(p)) => {
+					let entry = p.advance();
+					anim.switch_to(&entry.animation, &entry.params)
+				},
+				(ControlCommand::Next, None)         => anim.next(),
+				(ControlCommand::Animation(name), _) => anim.switch_to(&name, &Parameters::new()),
+			};
+
+			report_switch(res, &anim);
+
+			next_switch_instant = switch_period.map(|p| Instant::now() + p);
+		}
+
+		if standby {
+			// the input has been silent for too long: stop any signal processing until something
+			// else occurs at the input again
+			continue;
+		}
+
+		num_updates += 1;
+
+		if num_updates == debug_interval {
+			if config.debug {
+				print_analysis(&sigproc.borrow());
 			}
 
-			s.update_fft().unwrap();
+			num_updates = 0;
 		}
 
 		// call the periodic function in the user script
@@ -131,16 +271,12 @@ fn main()
 		if Instant::now() > next_send_instant {
 			let colorlists = anim.get_colorlist();
 
-			for i in 0..config::NUM_LEDS_TOTAL {
-				let strip = i / config::NUM_LEDS_PER_STRIP;
-				let led   = i % config::NUM_LEDS_PER_STRIP;
-
-				let r = udpproto.set_color(strip as u8,
-				                           led,
-				                           (colorlists[strip][led].r * 255.0) as u8,
-				                           (colorlists[strip][led].g * 255.0) as u8,
-				                           (colorlists[strip][led].b * 255.0) as u8,
-				                           (colorlists[strip][led].w * 255.0) as u8);
+			for (i, color) in colorlists.iter().enumerate() {
+				let r = output.set_color(i,
+				                         (color.r * 255.0) as u8,
+				                         (color.g * 255.0) as u8,
+				                         (color.b * 255.0) as u8,
+				                         (color.w * 255.0) as u8);
 
 				match r {
 					Ok(_) => (),
@@ -153,7 +289,7 @@ fn main()
 				}
 			}
 
-			match udpproto.commit() {
+			match output.commit() {
 					Ok(_) => (),
 					Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
 						// try again in one second
@@ -172,7 +308,7 @@ fn main()
 		}
 
 		let now = Instant::now();
-		if now < next_block_instant {
+		if now < next_block_instant && !run_fast {
 			sleep(next_block_instant - now);
 		}
 
@@ -180,3 +316,25 @@ fn main()
 	}
 
 }
+
+/// Print values of the signal analysis for debugging.
+fn print_analysis(s: &SignalProcessing)
+{
+	println!("--- Level: RMS {:.1} dBFS, peak {:.1} dBFS", s.get_rms_level(), s.get_peak_level());
+	println!("Onset strength: {:.3}", s.get_onset_strength());
+	println!("Tempo: {:.1} BPM (confidence {:.2}, phase {:.2})",
+	         s.get_tempo(), s.get_tempo_confidence(), s.get_beat_phase());
+
+	if s.has_separate_channels() {
+		println!("Stereo width: {:.2}", s.get_stereo_width());
+	}
+}
+
+fn report_switch(res: Result<bool, AnimationError>, anim: &Switcher)
+{
+	match res {
+		Ok(true)  => println!("Switching to animation: {}", anim.current_name()),
+		Ok(false) => println!("Applied parameters to animation: {}", anim.current_name()),
+		Err(e)    => println!("Warning: cannot switch animation: {}", e),
+	}
+}
@@ -0,0 +1,118 @@
+// vim: noet
+
+use crate::config::{Config, OutputProtocol, PixelFormat, WhiteConversion};
+
+pub mod udpproto;
+pub mod e131;
+pub mod artnet;
+pub mod ddp;
+pub mod opc;
+pub mod tpm2;
+pub mod adalight;
+pub mod router;
+
+/////////// Output Trait ////////////
+
+/*
+ * An output sends the LED colors to a controller. The colors of a frame are set one by one and
+ * then transmitted together by commit().
+ */
+pub trait Output {
+	/// Set the color of an LED. The index is counted over all strips.
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>;
+
+	/// Transmit the current frame.
+	fn commit(&mut self) -> std::io::Result<()>;
+}
+
+/// Construct the output selected in the configuration. If targets are configured, a router
+/// distributing the frame to all of them is returned.
+pub fn create(config: &Config) -> std::io::Result<Box<dyn Output>>
+{
+	if config.targets.is_empty() {
+		create_backend(config)
+	} else {
+		Ok(Box::new(router::Router::new(config)?))
+	}
+}
+
+fn create_backend(config: &Config) -> std::io::Result<Box<dyn Output>>
+{
+	match config.output {
+		OutputProtocol::Wled     => Ok(Box::new(udpproto::UdpProto::new(&config.udp_server_addr, config.num_leds_total(), &config.wled)?)),
+		OutputProtocol::E131     => Ok(Box::new(e131::E131::new(&config.udp_server_addr, config.num_leds_total(), &config.e131)?)),
+		OutputProtocol::ArtNet   => Ok(Box::new(artnet::ArtNet::new(&config.udp_server_addr, config.num_leds_total(), &config.artnet)?)),
+		OutputProtocol::Ddp      => Ok(Box::new(ddp::Ddp::new(&config.udp_server_addr, config.num_leds_total(), &config.ddp)?)),
+		OutputProtocol::Opc      => Ok(Box::new(opc::Opc::new(&config.udp_server_addr, config.num_strips, config.num_leds_per_strip)?)),
+		OutputProtocol::Tpm2Net  => Ok(Box::new(tpm2::Tpm2Net::new(&config.udp_server_addr, config.num_leds_total(), &config.tpm2)?)),
+		OutputProtocol::Tpm2     => Ok(Box::new(tpm2::Tpm2Serial::new(config.num_leds_total(), &config.tpm2)?)),
+		OutputProtocol::Adalight => Ok(Box::new(adalight::Adalight::new(config.num_leds_total(), &config.adalight)?)),
+	}
+}
+
+/// Convert an RGBW color to RGB by adding the white channel to all color channels.
+pub fn rgbw_to_rgb(r: u8, g: u8, b: u8, w: u8) -> (u8, u8, u8)
+{
+	(r.saturating_add(w), g.saturating_add(w), b.saturating_add(w))
+}
+
+/// Convert an RGBW color to RGB using the given conversion.
+pub fn convert_white(conversion: WhiteConversion, r: u8, g: u8, b: u8, w: u8) -> (u8, u8, u8)
+{
+	match conversion {
+		WhiteConversion::Add => rgbw_to_rgb(r, g, b, w),
+		WhiteConversion::Scale => {
+			let (r, g, b) = (r as u32 + w as u32, g as u32 + w as u32, b as u32 + w as u32);
+			let max = r.max(g).max(b).max(255);
+
+			((r * 255 / max) as u8, (g * 255 / max) as u8, (b * 255 / max) as u8)
+		},
+		WhiteConversion::Drop => (r, g, b),
+	}
+}
+
+/// Write one pixel in the given format to the beginning of `buf`.
+pub fn write_pixel(buf: &mut [u8], format: PixelFormat, r: u8, g: u8, b: u8, w: u8)
+{
+	match format {
+		PixelFormat::Rgb => {
+			let (r, g, b) = rgbw_to_rgb(r, g, b, w);
+			buf[.. 3].copy_from_slice(&[r, g, b]);
+		},
+		PixelFormat::Rgbw => {
+			buf[.. 4].copy_from_slice(&[r, g, b, w]);
+		},
+	}
+}
+
+/// Open a pseudo-terminal in raw mode, standing in for a serial device in tests. Returns the
+/// master side, the slave side (which has to stay open) and the path of the slave.
+#[cfg(test)]
+pub fn open_pty() -> (std::fs::File, std::fs::File, String)
+{
+	use std::ffi::CStr;
+	use std::os::unix::io::{AsRawFd, FromRawFd};
+
+	unsafe {
+		let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+		assert!(master >= 0, "cannot open a pseudo-terminal");
+		let master = std::fs::File::from_raw_fd(master);
+
+		assert_eq!(libc::grantpt(master.as_raw_fd()), 0);
+		assert_eq!(libc::unlockpt(master.as_raw_fd()), 0);
+
+		let mut name = [0 as libc::c_char; 64];
+		assert_eq!(libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()), 0);
+		let path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+		let slave = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+		// pass all bytes unchanged
+		let mut termios: libc::termios = std::mem::zeroed();
+		assert_eq!(libc::tcgetattr(slave.as_raw_fd(), &mut termios), 0);
+		libc::cfmakeraw(&mut termios);
+		assert_eq!(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios), 0);
+
+		(master, slave, path)
+	}
+}
@@ -0,0 +1,97 @@
+// vim: noet
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use crate::config::{AdalightConfig, WhiteConversion};
+use crate::output::{Output, convert_white};
+
+const HEADER_LEN: usize = 6;
+
+/*
+ * Writes frames for the Adalight firmware to a serial device. Each frame starts with "Ada", the
+ * number of LEDs minus one and a checksum, followed by the RGB data. As for TPM2, the port
+ * settings have to be configured beforehand.
+ */
+pub struct Adalight
+{
+	device: File,
+	white:  WhiteConversion,
+	frame:  Vec<u8>,
+}
+
+impl Adalight
+{
+	pub fn new(num_leds_total: usize, config: &AdalightConfig) -> std::io::Result<Adalight>
+	{
+		let device = OpenOptions::new().write(true).open(&config.device)?;
+
+		let mut frame = vec![0; HEADER_LEN + 3 * num_leds_total];
+
+		let [hi, lo] = ((num_leds_total - 1) as u16).to_be_bytes();
+
+		frame[0 .. 3].copy_from_slice(b"Ada");
+		frame[3] = hi;
+		frame[4] = lo;
+		frame[5] = hi ^ lo ^ 0x55;
+
+		Ok(Adalight {
+			device,
+			white: config.white,
+			frame,
+		})
+	}
+}
+
+impl Output for Adalight
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let offset = HEADER_LEN + 3 * led;
+
+		if offset + 3 > self.frame.len() {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range"));
+		}
+
+		let (r, g, b) = convert_white(self.white, r, g, b, w);
+		self.frame[offset .. offset + 3].copy_from_slice(&[r, g, b]);
+
+		Ok(())
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		self.device.write_all(&self.frame)?;
+		self.device.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn writes_header_and_pixels()
+	{
+		use std::io::Read;
+
+		let (mut master, _slave, path) = crate::output::open_pty();
+
+		let config = AdalightConfig {
+			device: path,
+			white:  WhiteConversion::Add,
+		};
+
+		let mut adalight = Adalight::new(300, &config).unwrap();
+		adalight.set_color(299, 1, 2, 3, 4).unwrap();
+		adalight.commit().unwrap();
+
+		let mut written = [0u8; HEADER_LEN + 3 * 300];
+		master.read_exact(&mut written).unwrap();
+
+		// 299 = 0x012b LEDs after the first
+		assert_eq!(written[.. HEADER_LEN], [b'A', b'd', b'a', 0x01, 0x2b, 0x01 ^ 0x2b ^ 0x55]);
+		assert_eq!(written[HEADER_LEN + 3 * 299 ..], [5, 6, 7]);
+	}
+}
@@ -0,0 +1,161 @@
+// vim: noet
+
+use std::net::{UdpSocket, SocketAddrV4, Ipv4Addr, ToSocketAddrs};
+
+use crate::config::{ArtNetConfig, PixelFormat};
+use crate::output::{Output, write_pixel};
+
+const DMX_UNIVERSE_SIZE: usize = 512;
+
+const ARTNET_ID: [u8; 8]     = *b"Art-Net\0";
+const ARTNET_PROT_VER: u16   = 14;
+
+const OP_DMX: u16  = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+
+// sizes and offsets in an ArtDmx packet (Art-Net 4 specification)
+const DMX_HEADER_LEN: usize  = 18;
+const SEQUENCE_OFFSET: usize = 12;
+const SYNC_PACKET_LEN: usize = 14;
+
+/*
+ * One universe with its pre-built ArtDmx packet.
+ */
+struct Universe
+{
+	packet: Vec<u8>,
+}
+
+/*
+ * Sender for Art-Net 4. The LEDs are mapped to consecutive port addresses, starting at the
+ * configured net, subnet and universe. A pixel never spans two universes.
+ */
+pub struct ArtNet
+{
+	socket: UdpSocket,
+	sync:   bool,
+
+	pixel_format:        PixelFormat,
+	pixels_per_universe: usize,
+
+	sequence:  u8,
+	universes: Vec<Universe>,
+}
+
+fn build_header(packet: &mut [u8], opcode: u16)
+{
+	packet[0 .. 8].copy_from_slice(&ARTNET_ID);
+	packet[8 .. 10].copy_from_slice(&opcode.to_le_bytes());
+	packet[10 .. 12].copy_from_slice(&ARTNET_PROT_VER.to_be_bytes());
+}
+
+fn build_dmx_packet(port_address: u16, num_channels: usize) -> Vec<u8>
+{
+	// the data length must be even
+	let len = num_channels + num_channels % 2;
+
+	let mut packet = vec![0u8; DMX_HEADER_LEN + len];
+
+	build_header(&mut packet, OP_DMX);
+
+	// packet[12] is the sequence number, packet[13] the physical input port
+	packet[14 .. 16].copy_from_slice(&port_address.to_le_bytes()); // SubUni, Net
+	packet[16 .. 18].copy_from_slice(&(len as u16).to_be_bytes());
+
+	packet
+}
+
+fn build_sync_packet() -> Vec<u8>
+{
+	let mut packet = vec![0u8; SYNC_PACKET_LEN];
+
+	build_header(&mut packet, OP_SYNC);
+
+	packet
+}
+
+impl ArtNet
+{
+	pub fn new(target_address: &str, num_leds_total: usize, config: &ArtNetConfig) -> std::io::Result<ArtNet>
+	{
+		let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+
+		socket.set_broadcast(config.broadcast)?;
+
+		let target = target_address.to_socket_addrs()?
+		                           .next()
+		                           .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot resolve target address"))?;
+		socket.connect(target)?;
+
+		let bytes_per_pixel = config.pixel_format.bytes_per_pixel();
+		let pixels_per_universe = DMX_UNIVERSE_SIZE / bytes_per_pixel;
+
+		// 15 bit port address: net (7 bits), subnet (4 bits), universe (4 bits)
+		let first_port_address = ((config.net as u16) << 8)
+			| ((config.subnet as u16) << 4)
+			| config.universe as u16;
+
+		let mut universes = Vec::new();
+		let mut remaining = num_leds_total;
+		let mut port_address = first_port_address;
+
+		while remaining > 0 {
+			if port_address > 0x7fff {
+				return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Too many LEDs for the Art-Net port address range"));
+			}
+
+			let pixels = remaining.min(pixels_per_universe);
+
+			universes.push(Universe {
+				packet: build_dmx_packet(port_address, pixels * bytes_per_pixel),
+			});
+
+			remaining -= pixels;
+			port_address += 1;
+		}
+
+		Ok(ArtNet {
+			socket,
+			sync: config.sync,
+			pixel_format: config.pixel_format,
+			pixels_per_universe,
+			sequence: 1,
+			universes,
+		})
+	}
+}
+
+impl Output for ArtNet
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let universe = led / self.pixels_per_universe;
+		let offset = DMX_HEADER_LEN + (led % self.pixels_per_universe) * bytes_per_pixel;
+
+		match self.universes.get_mut(universe) {
+			Some(u) if offset + bytes_per_pixel <= u.packet.len() => {
+				write_pixel(&mut u.packet[offset ..], self.pixel_format, r, g, b, w);
+				Ok(())
+			},
+			_ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range")),
+		}
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		for universe in self.universes.iter_mut() {
+			universe.packet[SEQUENCE_OFFSET] = self.sequence;
+			self.socket.send(&universe.packet)?;
+		}
+
+		// 0 disables sequencing, so skip it
+		self.sequence = self.sequence.wrapping_add(1).max(1);
+
+		if self.sync {
+			self.socket.send(&build_sync_packet())?;
+		}
+
+		Ok(())
+	}
+}
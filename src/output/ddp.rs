@@ -0,0 +1,100 @@
+// vim: noet
+
+use std::net::{UdpSocket, SocketAddrV4, Ipv4Addr};
+
+use crate::config::{DdpConfig, PixelFormat};
+use crate::output::{Output, write_pixel};
+
+// length of the DDP header without the optional timecode
+const HEADER_LEN: usize = 10;
+
+// divisible by 3 and 4, so pixels never span two packets
+const MAX_DATA_LEN: usize = 1440;
+
+const FLAGS_VERSION_1: u8 = 0x40;
+const FLAGS_PUSH: u8      = 0x01;
+
+// data type: RGB or RGBW with 8 bits per channel
+const TYPE_RGB8: u8  = 0x0b;
+const TYPE_RGBW8: u8 = 0x1b;
+
+const DEST_DEFAULT_OUTPUT: u8 = 1;
+
+/*
+ * Sender for the Distributed Display Protocol. A frame is split into packets carrying their data
+ * offset; the push flag on the last packet tells the receiver to display the frame.
+ */
+pub struct Ddp
+{
+	socket:       UdpSocket,
+	pixel_format: PixelFormat,
+
+	data:   Vec<u8>,
+	packet: Vec<u8>,
+
+	// 4 bit sequence number, 0 is not used
+	sequence: u8,
+}
+
+impl Ddp
+{
+	pub fn new(target_address: &str, num_leds_total: usize, config: &DdpConfig) -> std::io::Result<Ddp>
+	{
+		let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+		socket.connect(target_address)?;
+
+		Ok(Ddp {
+			socket,
+			pixel_format: config.pixel_format,
+			data: vec![0; num_leds_total * config.pixel_format.bytes_per_pixel()],
+			packet: Vec::with_capacity(HEADER_LEN + MAX_DATA_LEN),
+			sequence: 1,
+		})
+	}
+}
+
+impl Output for Ddp
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let offset = led * bytes_per_pixel;
+
+		if offset + bytes_per_pixel > self.data.len() {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range"));
+		}
+
+		write_pixel(&mut self.data[offset ..], self.pixel_format, r, g, b, w);
+
+		Ok(())
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		let data_type = match self.pixel_format {
+			PixelFormat::Rgb  => TYPE_RGB8,
+			PixelFormat::Rgbw => TYPE_RGBW8,
+		};
+
+		let num_chunks = self.data.len().div_ceil(MAX_DATA_LEN);
+
+		for (i, chunk) in self.data.chunks(MAX_DATA_LEN).enumerate() {
+			let flags = match i + 1 == num_chunks {
+				true  => FLAGS_VERSION_1 | FLAGS_PUSH,
+				false => FLAGS_VERSION_1,
+			};
+
+			self.packet.clear();
+			self.packet.extend_from_slice(&[flags, self.sequence, data_type, DEST_DEFAULT_OUTPUT]);
+			self.packet.extend_from_slice(&((i * MAX_DATA_LEN) as u32).to_be_bytes());
+			self.packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+			self.packet.extend_from_slice(chunk);
+
+			self.socket.send(&self.packet)?;
+		}
+
+		self.sequence = self.sequence % 15 + 1;
+
+		Ok(())
+	}
+}
@@ -0,0 +1,254 @@
+// vim: noet
+
+use std::net::{UdpSocket, SocketAddr, SocketAddrV4, Ipv4Addr, ToSocketAddrs};
+
+use rand::Rng;
+
+use crate::config::{E131Config, PixelFormat};
+use crate::output::{Output, write_pixel};
+
+const E131_PORT: u16 = 5568;
+
+const DMX_UNIVERSE_SIZE: usize = 512;
+
+// sizes and offsets in an E1.31 data packet (ANSI E1.31-2016, section 4)
+const HEADER_LEN: usize            = 126;
+const ROOT_LAYER_START: usize      = 16;
+const FRAMING_LAYER_START: usize   = 38;
+const DMP_LAYER_START: usize       = 115;
+const SOURCE_NAME_OFFSET: usize    = 44;
+const SOURCE_NAME_LEN: usize       = 64;
+const PRIORITY_OFFSET: usize       = 108;
+const SEQUENCE_OFFSET: usize       = 111;
+const UNIVERSE_OFFSET: usize       = 113;
+const PROPERTY_COUNT_OFFSET: usize = 123;
+
+const ACN_PACKET_IDENTIFIER: [u8; 12] = *b"ASC-E1.17\0\0\0";
+
+const VECTOR_ROOT_E131_DATA: u32   = 0x00000004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x00000002;
+const VECTOR_DMP_SET_PROPERTY: u8  = 0x02;
+
+/*
+ * One universe with its pre-built packet.
+ */
+struct Universe
+{
+	number:   u16,
+	sequence: u8,
+	packet:   Vec<u8>,
+}
+
+/*
+ * Sender for E1.31 (sACN). The LEDs are mapped to consecutive universes, starting at the
+ * configured universe and channel. A pixel never spans two universes.
+ */
+pub struct E131
+{
+	socket:    UdpSocket,
+	multicast: bool,
+
+	pixel_format:        PixelFormat,
+	pixels_per_universe: usize,
+	first_pixel_offset:  usize,
+
+	universes: Vec<Universe>,
+}
+
+fn put_flags_and_length(packet: &mut [u8], offset: usize)
+{
+	let len = (packet.len() - offset) as u16;
+	packet[offset .. offset + 2].copy_from_slice(&(0x7000 | len).to_be_bytes());
+}
+
+fn build_packet(cid: &[u8; 16], config: &E131Config, universe: u16, num_slots: usize) -> Vec<u8>
+{
+	let mut packet = vec![0u8; HEADER_LEN + num_slots];
+
+	// root layer
+	packet[0 .. 2].copy_from_slice(&0x0010u16.to_be_bytes()); // preamble size
+	packet[4 .. 16].copy_from_slice(&ACN_PACKET_IDENTIFIER);
+	put_flags_and_length(&mut packet, ROOT_LAYER_START);
+	packet[18 .. 22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+	packet[22 .. 38].copy_from_slice(cid);
+
+	// framing layer
+	put_flags_and_length(&mut packet, FRAMING_LAYER_START);
+	packet[40 .. 44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+
+	let name = config.source_name.as_bytes();
+	let name_len = name.len().min(SOURCE_NAME_LEN - 1); // keep the terminating null byte
+	packet[SOURCE_NAME_OFFSET .. SOURCE_NAME_OFFSET + name_len].copy_from_slice(&name[.. name_len]);
+
+	packet[PRIORITY_OFFSET] = config.priority;
+	packet[UNIVERSE_OFFSET .. UNIVERSE_OFFSET + 2].copy_from_slice(&universe.to_be_bytes());
+
+	// DMP layer
+	put_flags_and_length(&mut packet, DMP_LAYER_START);
+	packet[117] = VECTOR_DMP_SET_PROPERTY;
+	packet[118] = 0xa1; // address type & data type
+	packet[121 .. 123].copy_from_slice(&1u16.to_be_bytes()); // address increment
+	packet[PROPERTY_COUNT_OFFSET .. PROPERTY_COUNT_OFFSET + 2].copy_from_slice(&((num_slots + 1) as u16).to_be_bytes());
+	// packet[125] is the DMX start code, which is always 0
+
+	packet
+}
+
+fn multicast_address(universe: u16) -> SocketAddr
+{
+	let [hi, lo] = universe.to_be_bytes();
+	SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, hi, lo), E131_PORT))
+}
+
+impl E131
+{
+	pub fn new(target_address: &str, num_leds_total: usize, config: &E131Config) -> std::io::Result<E131>
+	{
+		let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+
+		if !config.multicast {
+			let target = target_address.to_socket_addrs()?
+			                           .next()
+			                           .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot resolve target address"))?;
+			socket.connect(target)?;
+		}
+
+		let bytes_per_pixel = config.pixel_format.bytes_per_pixel();
+		let pixels_per_universe = DMX_UNIVERSE_SIZE / bytes_per_pixel;
+		let first_pixel_offset = config.start_channel as usize - 1;
+		let pixels_in_first_universe = (DMX_UNIVERSE_SIZE - first_pixel_offset) / bytes_per_pixel;
+
+		let mut cid = [0u8; 16];
+		rand::thread_rng().fill(&mut cid);
+
+		// distribute the pixels over the universes
+		let mut universes = Vec::new();
+		let mut remaining = num_leds_total;
+		let mut number = config.universe;
+
+		while remaining > 0 {
+			let (offset, capacity) = match universes.is_empty() {
+				true  => (first_pixel_offset, pixels_in_first_universe),
+				false => (0, pixels_per_universe),
+			};
+
+			let pixels = remaining.min(capacity);
+
+			universes.push(Universe {
+				number,
+				sequence: 0,
+				packet: build_packet(&cid, config, number, offset + pixels * bytes_per_pixel),
+			});
+
+			remaining -= pixels;
+			number += 1;
+		}
+
+		Ok(E131 {
+			socket,
+			multicast: config.multicast,
+			pixel_format: config.pixel_format,
+			pixels_per_universe,
+			first_pixel_offset,
+			universes,
+		})
+	}
+
+	/// Find the universe and the byte offset in its packet for the given LED.
+	fn locate(&self, led: usize) -> (usize, usize)
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let pixels_in_first_universe = (DMX_UNIVERSE_SIZE - self.first_pixel_offset) / bytes_per_pixel;
+
+		if led < pixels_in_first_universe {
+			(0, HEADER_LEN + self.first_pixel_offset + led * bytes_per_pixel)
+		} else {
+			let idx = led - pixels_in_first_universe;
+			(1 + idx / self.pixels_per_universe, HEADER_LEN + (idx % self.pixels_per_universe) * bytes_per_pixel)
+		}
+	}
+}
+
+impl Output for E131
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let (universe, offset) = self.locate(led);
+
+		match self.universes.get_mut(universe) {
+			Some(u) if offset < u.packet.len() => {
+				write_pixel(&mut u.packet[offset ..], self.pixel_format, r, g, b, w);
+				Ok(())
+			},
+			_ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range")),
+		}
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		for universe in self.universes.iter_mut() {
+			universe.packet[SEQUENCE_OFFSET] = universe.sequence;
+			universe.sequence = universe.sequence.wrapping_add(1);
+
+			if self.multicast {
+				self.socket.send_to(&universe.packet, multicast_address(universe.number))?;
+			} else {
+				self.socket.send(&universe.packet)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	use std::time::Duration;
+
+	#[test]
+	fn sends_valid_packet_to_local_listener()
+	{
+		let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+		listener.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+		let config = E131Config {
+			universe: 7,
+			priority: 150,
+			..E131Config::default()
+		};
+
+		let mut e131 = E131::new(&listener.local_addr().unwrap().to_string(), 2, &config).unwrap();
+		e131.set_color(1, 10, 20, 30, 0).unwrap();
+		e131.commit().unwrap();
+
+		let mut buf = [0u8; 1024];
+		let len = listener.recv(&mut buf).unwrap();
+		let packet = &buf[.. len];
+
+		assert_eq!(len, HEADER_LEN + 2 * 3);
+
+		// root layer
+		assert_eq!(packet[0 .. 2], [0x00, 0x10]);
+		assert_eq!(packet[4 .. 16], ACN_PACKET_IDENTIFIER);
+		assert_eq!(u16::from_be_bytes([packet[16], packet[17]]), 0x7000 | (len - ROOT_LAYER_START) as u16);
+		assert_eq!(packet[18 .. 22], VECTOR_ROOT_E131_DATA.to_be_bytes());
+
+		// framing layer
+		assert_eq!(u16::from_be_bytes([packet[38], packet[39]]), 0x7000 | (len - FRAMING_LAYER_START) as u16);
+		assert_eq!(packet[40 .. 44], VECTOR_E131_DATA_PACKET.to_be_bytes());
+		assert_eq!(&packet[SOURCE_NAME_OFFSET .. SOURCE_NAME_OFFSET + 11], b"musiclight\0");
+		assert_eq!(packet[PRIORITY_OFFSET], 150);
+		assert_eq!(packet[SEQUENCE_OFFSET], 0);
+		assert_eq!(packet[UNIVERSE_OFFSET .. UNIVERSE_OFFSET + 2], [0, 7]);
+
+		// DMP layer
+		assert_eq!(u16::from_be_bytes([packet[115], packet[116]]), 0x7000 | (len - DMP_LAYER_START) as u16);
+		assert_eq!(packet[117], VECTOR_DMP_SET_PROPERTY);
+		assert_eq!(packet[118], 0xa1);
+		assert_eq!(packet[PROPERTY_COUNT_OFFSET .. PROPERTY_COUNT_OFFSET + 2], [0, 7]);
+		assert_eq!(packet[125 ..], [0, 0, 0, 0, 10, 20, 30]);
+	}
+}
@@ -0,0 +1,164 @@
+// vim: noet
+
+use std::io::Write;
+use std::net::{TcpStream, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::output::{Output, rgbw_to_rgb};
+
+const HEADER_LEN: usize = 4;
+const CMD_SET_PIXEL_COLORS: u8 = 0;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+const WRITE_TIMEOUT: Duration   = Duration::from_millis(100);
+
+// delay before the next connection attempt, doubled after each failure
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/*
+ * Open Pixel Control client. Each strip is sent as a set-pixel-colors message on its own channel,
+ * starting at channel 1. If the connection is lost, frames are dropped and the client reconnects
+ * with exponential backoff.
+ */
+pub struct Opc
+{
+	target: SocketAddr,
+	stream: Option<TcpStream>,
+
+	backoff:      Duration,
+	next_attempt: Instant,
+
+	num_leds_per_strip: usize,
+
+	// one message per strip, sent as one buffer
+	buffer: Vec<u8>,
+}
+
+impl Opc
+{
+	pub fn new(target_address: &str, num_strips: usize, num_leds_per_strip: usize) -> std::io::Result<Opc>
+	{
+		let target = target_address.to_socket_addrs()?
+		                           .next()
+		                           .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot resolve target address"))?;
+
+		let data_len = 3 * num_leds_per_strip;
+
+		if data_len > u16::MAX as usize || num_strips > 255 {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Too many LEDs for Open Pixel Control"));
+		}
+
+		let message_len = HEADER_LEN + data_len;
+		let mut buffer = vec![0; num_strips * message_len];
+
+		for (strip, message) in buffer.chunks_mut(message_len).enumerate() {
+			message[0] = (strip + 1) as u8;
+			message[1] = CMD_SET_PIXEL_COLORS;
+			message[2 .. 4].copy_from_slice(&(data_len as u16).to_be_bytes());
+		}
+
+		Ok(Opc {
+			target,
+			stream: None,
+			backoff: BACKOFF_MIN,
+			next_attempt: Instant::now(),
+			num_leds_per_strip,
+			buffer,
+		})
+	}
+
+	fn connect(&mut self) -> std::io::Result<TcpStream>
+	{
+		let stream = TcpStream::connect_timeout(&self.target, CONNECT_TIMEOUT)?;
+
+		stream.set_nodelay(true)?;
+		stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+		Ok(stream)
+	}
+
+	fn disconnected(&mut self, e: std::io::Error)
+	{
+		println!("OPC: connection to {} failed: {}; retrying in {:.1} s", self.target, e, self.backoff.as_secs_f32());
+
+		self.stream = None;
+		self.next_attempt = Instant::now() + self.backoff;
+		self.backoff = (self.backoff * 2).min(BACKOFF_MAX);
+	}
+}
+
+impl Output for Opc
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let strip = led / self.num_leds_per_strip;
+		let message_len = HEADER_LEN + 3 * self.num_leds_per_strip;
+		let offset = strip * message_len + HEADER_LEN + 3 * (led % self.num_leds_per_strip);
+
+		if offset + 3 > self.buffer.len() {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range"));
+		}
+
+		let (r, g, b) = rgbw_to_rgb(r, g, b, w);
+		self.buffer[offset .. offset + 3].copy_from_slice(&[r, g, b]);
+
+		Ok(())
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		if self.stream.is_none() {
+			if Instant::now() < self.next_attempt {
+				return Ok(());
+			}
+
+			match self.connect() {
+				Ok(stream) => {
+					println!("OPC: connected to {}", self.target);
+					self.stream = Some(stream);
+					self.backoff = BACKOFF_MIN;
+				},
+				Err(e) => {
+					self.disconnected(e);
+					return Ok(());
+				},
+			}
+		}
+
+		if let Some(stream) = self.stream.as_mut() {
+			if let Err(e) = stream.write_all(&self.buffer) {
+				self.disconnected(e);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	use std::io::Read;
+	use std::net::TcpListener;
+
+	#[test]
+	fn sends_one_message_per_strip()
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+		let mut opc = Opc::new(&listener.local_addr().unwrap().to_string(), 2, 2).unwrap();
+		opc.set_color(0, 1, 2, 3, 0).unwrap();
+		opc.set_color(3, 4, 5, 6, 10).unwrap();
+		opc.commit().unwrap();
+
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut buf = [0u8; 2 * (HEADER_LEN + 6)];
+		stream.read_exact(&mut buf).unwrap();
+
+		assert_eq!(buf, [1, CMD_SET_PIXEL_COLORS, 0, 6, 1, 2, 3, 0, 0, 0,
+		                 2, CMD_SET_PIXEL_COLORS, 0, 6, 0, 0, 0, 14, 15, 16]);
+	}
+}
@@ -0,0 +1,103 @@
+// vim: noet
+
+use std::ops::Range;
+
+use crate::config::{Config, PixelOrder};
+use crate::output::{Output, rgbw_to_rgb};
+
+/*
+ * One output target with the part of the frame it receives.
+ */
+struct Target
+{
+	output: Box<dyn Output>,
+
+	range:       Range<usize>,
+	pixel_order: PixelOrder,
+	reverse:     bool,
+	offset:      usize,
+}
+
+/*
+ * Distributes the frame to several outputs. Each target receives a range of the global LED
+ * indices, mapped to its own LED indices.
+ */
+pub struct Router
+{
+	targets: Vec<Target>,
+}
+
+impl Router
+{
+	pub fn new(config: &Config) -> std::io::Result<Router>
+	{
+		let mut targets = Vec::with_capacity(config.targets.len());
+
+		for target in config.targets.iter() {
+			let range = target.led_range(config);
+			let output = super::create_backend(&target.output_config(config))?;
+
+			targets.push(Target {
+				output,
+				range,
+				pixel_order: target.pixel_order,
+				reverse: target.reverse,
+				offset: target.offset,
+			});
+		}
+
+		Ok(Router {
+			targets,
+		})
+	}
+}
+
+impl Output for Router
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		for target in self.targets.iter_mut() {
+			if !target.range.contains(&led) {
+				continue;
+			}
+
+			let mut idx = led - target.range.start;
+
+			if target.reverse {
+				idx = target.range.len() - 1 - idx;
+			}
+
+			let (r, g, b, w) = match target.pixel_order {
+				PixelOrder::Rgb => {
+					let (r, g, b) = rgbw_to_rgb(r, g, b, w);
+					(r, g, b, 0)
+				},
+				PixelOrder::Grb => {
+					let (r, g, b) = rgbw_to_rgb(r, g, b, w);
+					(g, r, b, 0)
+				},
+				PixelOrder::Rgbw => (r, g, b, w),
+			};
+
+			target.output.set_color(target.offset + idx, r, g, b, w)?;
+		}
+
+		Ok(())
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		// a failing target must not stop the others, so report only the first error
+		let mut result = Ok(());
+
+		for target in self.targets.iter_mut() {
+			let r = target.output.commit();
+
+			if result.is_ok() {
+				result = r;
+			}
+		}
+
+		result
+	}
+}
@@ -0,0 +1,229 @@
+// vim: noet
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{UdpSocket, SocketAddrV4, Ipv4Addr};
+
+use crate::config::{PixelFormat, Tpm2Config};
+use crate::output::{Output, write_pixel};
+
+const TPM2_SERIAL_START: u8 = 0xc9;
+const TPM2_NET_START: u8    = 0x9c;
+const TPM2_DATA_FRAME: u8   = 0xda;
+const TPM2_END: u8          = 0x36;
+
+const NET_HEADER_LEN: usize = 6;
+
+// divisible by 3 and 4, so pixels never span two packets
+const MAX_NET_DATA_LEN: usize = 1488;
+
+/*
+ * Pixel data of one frame, shared by the network and the serial variant.
+ */
+struct FrameData
+{
+	pixel_format: PixelFormat,
+	data: Vec<u8>,
+}
+
+impl FrameData
+{
+	fn new(num_leds_total: usize, pixel_format: PixelFormat) -> FrameData
+	{
+		FrameData {
+			pixel_format,
+			data: vec![0; num_leds_total * pixel_format.bytes_per_pixel()],
+		}
+	}
+
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let offset = led * bytes_per_pixel;
+
+		if offset + bytes_per_pixel > self.data.len() {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range"));
+		}
+
+		write_pixel(&mut self.data[offset ..], self.pixel_format, r, g, b, w);
+
+		Ok(())
+	}
+}
+
+/*
+ * Sender for TPM2.net. Frames longer than one packet are split; each packet carries its number
+ * and the total number of packets in the frame.
+ */
+pub struct Tpm2Net
+{
+	socket: UdpSocket,
+	frame:  FrameData,
+	packet: Vec<u8>,
+}
+
+impl Tpm2Net
+{
+	pub fn new(target_address: &str, num_leds_total: usize, config: &Tpm2Config) -> std::io::Result<Tpm2Net>
+	{
+		let frame = FrameData::new(num_leds_total, config.pixel_format);
+
+		// packet numbers are a single byte
+		if frame.data.len().div_ceil(MAX_NET_DATA_LEN) > 255 {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Too many LEDs for TPM2.net"));
+		}
+
+		let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+		socket.connect(target_address)?;
+
+		Ok(Tpm2Net {
+			socket,
+			frame,
+			packet: Vec::with_capacity(NET_HEADER_LEN + MAX_NET_DATA_LEN + 1),
+		})
+	}
+}
+
+impl Output for Tpm2Net
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		self.frame.set_color(led, r, g, b, w)
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		let num_packets = self.frame.data.len().div_ceil(MAX_NET_DATA_LEN);
+
+		for (i, chunk) in self.frame.data.chunks(MAX_NET_DATA_LEN).enumerate() {
+			self.packet.clear();
+			self.packet.extend_from_slice(&[TPM2_NET_START, TPM2_DATA_FRAME]);
+			self.packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+			self.packet.extend_from_slice(&[(i + 1) as u8, num_packets as u8]);
+			self.packet.extend_from_slice(chunk);
+			self.packet.push(TPM2_END);
+
+			self.socket.send(&self.packet)?;
+		}
+
+		Ok(())
+	}
+}
+
+/*
+ * Writes TPM2 frames to a serial device. The port settings (e.g. the baud rate) are not changed
+ * and have to be configured beforehand, for example with stty. A frame longer than
+ * `max_frame_len` bytes of pixel data is split into several consecutive frames.
+ */
+pub struct Tpm2Serial
+{
+	device: File,
+	frame:  FrameData,
+	buffer: Vec<u8>,
+
+	max_frame_len: usize,
+}
+
+impl Tpm2Serial
+{
+	pub fn new(num_leds_total: usize, config: &Tpm2Config) -> std::io::Result<Tpm2Serial>
+	{
+		let device = OpenOptions::new().write(true).open(&config.device)?;
+
+		let bytes_per_pixel = config.pixel_format.bytes_per_pixel();
+
+		// never split a pixel
+		let max_frame_len = (config.max_frame_len / bytes_per_pixel) * bytes_per_pixel;
+
+		Ok(Tpm2Serial {
+			device,
+			frame: FrameData::new(num_leds_total, config.pixel_format),
+			buffer: Vec::new(),
+			max_frame_len,
+		})
+	}
+}
+
+impl Output for Tpm2Serial
+{
+	fn set_color(&mut self, led: usize, r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		self.frame.set_color(led, r, g, b, w)
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		self.buffer.clear();
+
+		for chunk in self.frame.data.chunks(self.max_frame_len) {
+			self.buffer.extend_from_slice(&[TPM2_SERIAL_START, TPM2_DATA_FRAME]);
+			self.buffer.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+			self.buffer.extend_from_slice(chunk);
+			self.buffer.push(TPM2_END);
+		}
+
+		self.device.write_all(&self.buffer)?;
+		self.device.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	use std::time::Duration;
+
+	#[test]
+	fn splits_net_frames_into_numbered_packets()
+	{
+		let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+		listener.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+		// 600 RGB pixels need two packets
+		let mut tpm2 = Tpm2Net::new(&listener.local_addr().unwrap().to_string(), 600, &Tpm2Config::default()).unwrap();
+		tpm2.set_color(599, 1, 2, 3, 0).unwrap();
+		tpm2.commit().unwrap();
+
+		let mut buf = [0u8; 2048];
+
+		for (number, data_len) in [(1, MAX_NET_DATA_LEN), (2, 600 * 3 - MAX_NET_DATA_LEN)] {
+			let len = listener.recv(&mut buf).unwrap();
+
+			assert_eq!(len, NET_HEADER_LEN + data_len + 1);
+			assert_eq!(buf[.. 2], [TPM2_NET_START, TPM2_DATA_FRAME]);
+			assert_eq!(buf[2 .. 4], (data_len as u16).to_be_bytes());
+			assert_eq!(buf[4 .. 6], [number, 2]);
+			assert_eq!(buf[len - 1], TPM2_END);
+		}
+
+		assert_eq!(buf[NET_HEADER_LEN + 309 .. NET_HEADER_LEN + 312], [1, 2, 3]);
+	}
+
+	#[test]
+	fn splits_serial_frames_at_whole_pixels()
+	{
+		use std::io::Read;
+
+		let (mut master, _slave, path) = crate::output::open_pty();
+
+		// 10 bytes are rounded down to two RGBW pixels per frame
+		let config = Tpm2Config {
+			pixel_format: PixelFormat::Rgbw,
+			device: path,
+			max_frame_len: 10,
+		};
+
+		let mut tpm2 = Tpm2Serial::new(5, &config).unwrap();
+		tpm2.set_color(2, 1, 2, 3, 4).unwrap();
+		tpm2.set_color(4, 5, 6, 7, 8).unwrap();
+		tpm2.commit().unwrap();
+
+		let mut buf = [0u8; 3 * 5 + 8 + 8 + 4];
+		master.read_exact(&mut buf).unwrap();
+
+		assert_eq!(buf[.. 13], [TPM2_SERIAL_START, TPM2_DATA_FRAME, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, TPM2_END]);
+		assert_eq!(buf[13 .. 26], [TPM2_SERIAL_START, TPM2_DATA_FRAME, 0, 8, 1, 2, 3, 4, 0, 0, 0, 0, TPM2_END]);
+		assert_eq!(buf[26 ..], [TPM2_SERIAL_START, TPM2_DATA_FRAME, 0, 4, 5, 6, 7, 8, TPM2_END]);
+	}
+}
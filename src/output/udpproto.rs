@@ -0,0 +1,114 @@
+// vim: noet
+
+use std::net::UdpSocket;
+use std::net::SocketAddrV4;
+use std::net::Ipv4Addr;
+
+use crate::config::{PixelFormat, WledConfig};
+use crate::output::{Output, write_pixel};
+
+const MAX_PACKET_LEN: usize = 1470;
+const TIMEOUT_SEC: u8 = 3;
+const WLED_MODE_DRGB: u8 = 2;
+const WLED_MODE_DRGBW: u8 = 3;
+const WLED_MODE_DNRGB: u8 = 4;
+
+struct Command
+{
+	cmd:   u8,
+	strip: u8,
+	led:   u8,
+	data: [u8; 4],
+}
+
+/*
+ * Sender for the WLED UDP realtime protocol. If all LEDs fit into one packet, DRGB or DRGBW is
+ * used. Otherwise, the frame is split into DNRGB packets, which carry the index of their first LED.
+ */
+pub struct UdpProto
+{
+	socket:          UdpSocket,
+	pixel_format:    PixelFormat,
+	header_len:      usize,
+	leds_per_packet: usize,
+	packets:         Vec<Vec<u8>>,
+}
+
+impl UdpProto
+{
+	pub fn new(target_address: &str, num_leds_total: usize, config: &WledConfig) -> std::io::Result<UdpProto>
+	{
+		let single_packet_leds = (MAX_PACKET_LEN - 2) / config.pixel_format.bytes_per_pixel();
+
+		let (mode, pixel_format, header_len) = if num_leds_total <= single_packet_leds {
+			match config.pixel_format {
+				PixelFormat::Rgb  => (WLED_MODE_DRGB, PixelFormat::Rgb, 2),
+				PixelFormat::Rgbw => (WLED_MODE_DRGBW, PixelFormat::Rgbw, 2),
+			}
+		} else {
+			// DNRGB has no white channel
+			(WLED_MODE_DNRGB, PixelFormat::Rgb, 4)
+		};
+
+		let leds_per_packet = (MAX_PACKET_LEN - header_len) / pixel_format.bytes_per_pixel();
+
+		let mut packets = Vec::new();
+
+		for start in (0..num_leds_total).step_by(leds_per_packet) {
+			if start > u16::MAX as usize {
+				return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Too many LEDs for the WLED protocol"));
+			}
+
+			let num_leds = leds_per_packet.min(num_leds_total - start);
+
+			let mut packet = vec![0; header_len + num_leds * pixel_format.bytes_per_pixel()];
+
+			packet[0] = mode;
+			packet[1] = TIMEOUT_SEC;
+
+			if mode == WLED_MODE_DNRGB {
+				packet[2 .. 4].copy_from_slice(&(start as u16).to_be_bytes());
+			}
+
+			packets.push(packet);
+		}
+
+		let u = UdpProto {
+			socket: UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?,
+			pixel_format,
+			header_len,
+			leds_per_packet,
+			packets,
+		};
+
+		u.socket.connect(target_address)?;
+
+		Ok(u)
+	}
+}
+
+impl Output for UdpProto
+{
+	fn set_color(&mut self, led: usize,
+		r: u8, g: u8, b: u8, w: u8) -> std::io::Result<()>
+	{
+		let bytes_per_pixel = self.pixel_format.bytes_per_pixel();
+		let offset = self.header_len + (led % self.leds_per_packet) * bytes_per_pixel;
+
+		match self.packets.get_mut(led / self.leds_per_packet) {
+			Some(packet) if offset + bytes_per_pixel <= packet.len() => {
+				write_pixel(&mut packet[offset ..], self.pixel_format, r, g, b, w);
+				Ok( () )
+			},
+			_ => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "LED index out of range")),
+		}
+	}
+
+	fn commit(&mut self) -> std::io::Result<()>
+	{
+		for packet in self.packets.iter() {
+			self.socket.send(packet)?;
+		}
+		Ok( () )
+	}
+}
@@ -0,0 +1,102 @@
+// vim: noet
+
+use rand::seq::SliceRandom;
+
+use crate::config::{PlaylistConfig, PlaylistEntry, PlaylistOrder};
+use crate::signal_processing::SignalProcessing;
+
+/*
+ * The playlist cycles through a list of animations. Each entry stays active until its duration
+ * has elapsed, a number of beats was counted or a silence gap occurred.
+ */
+pub struct Playlist
+{
+	entries: Vec<PlaylistEntry>,
+	order: PlaylistOrder,
+
+	// indices into entries in the order in which they are played
+	sequence: Vec<usize>,
+	pos: usize,
+
+	// progress of the current entry
+	elapsed: f32,
+	beats: u32,
+}
+
+impl Playlist
+{
+	pub fn new(config: &PlaylistConfig) -> Playlist
+	{
+		let mut p = Playlist {
+			entries: config.entries.clone(),
+			order: config.order,
+			sequence: (0..config.entries.len()).collect(),
+			pos: 0,
+			elapsed: 0.0,
+			beats: 0,
+		};
+
+		if p.order == PlaylistOrder::Shuffle {
+			p.sequence.shuffle(&mut rand::thread_rng());
+		}
+
+		p
+	}
+
+	pub fn current(&self) -> &PlaylistEntry
+	{
+		&self.entries[self.sequence[self.pos]]
+	}
+
+	/// Go to the next entry and return it.
+	pub fn advance(&mut self) -> &PlaylistEntry
+	{
+		self.pos += 1;
+
+		if self.pos >= self.sequence.len() {
+			self.pos = 0;
+
+			if self.order == PlaylistOrder::Shuffle && self.sequence.len() > 1 {
+				let last = self.sequence[self.sequence.len() - 1];
+
+				self.sequence.shuffle(&mut rand::thread_rng());
+
+				// do not play the same entry twice in a row
+				if self.sequence[0] == last {
+					self.sequence.swap(0, 1);
+				}
+			}
+		}
+
+		self.elapsed = 0.0;
+		self.beats = 0;
+
+		self.current()
+	}
+
+	/// Update the progress of the current entry. Must be called once per signal processing update,
+	/// `dt` seconds after the previous call. Returns the new entry if the playlist advanced.
+	pub fn update(&mut self, sigproc: &SignalProcessing, dt: f32) -> Option<&PlaylistEntry>
+	{
+		self.elapsed += dt;
+
+		let silent_time = sigproc.get_silence_duration();
+
+		if sigproc.is_beat() {
+			self.beats += 1;
+		}
+
+		let entry = self.current();
+
+		let done = entry.duration.is_some_and(|d| self.elapsed >= d)
+			|| entry.beats.is_some_and(|b| self.beats >= b)
+			// only trigger once per silence gap
+			|| entry.silence.is_some_and(|s| silent_time >= s && silent_time - dt < s);
+
+		if done {
+			Some(self.advance())
+		} else {
+			None
+		}
+	}
+}
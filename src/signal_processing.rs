@@ -5,7 +5,40 @@
 use fftw::array::AlignedVec;
 use fftw::plan::*;
 use fftw::types::*;
-use std::f32::consts::PI;
+use crate::config::{Config, StereoMode};
+
+pub mod silence;
+pub mod onset;
+pub mod tempo;
+pub mod filterbank;
+pub mod window;
+pub mod agc;
+
+use silence::SilenceDetector;
+use onset::{OnsetBand, OnsetDetector};
+use tempo::TempoTracker;
+use filterbank::Filterbank;
+
+// full scale of the samples as imported from i16
+const I16_FULL_SCALE: f32 = 32768.0;
+
+/*
+ * A longer FFT over the most recent samples for a finer frequency resolution in the bass range.
+ * Its magnitudes are scaled to match those of the main spectrum for a sine.
+ */
+struct BassFft
+{
+	crossover: f32,
+
+	window: Vec<f32>,
+
+	input:  AlignedVec<f32>,
+	output: AlignedVec<c32>,
+
+	plan: R2CPlan32,
+
+	absolute: Vec<f32>,
+}
 
 pub struct SignalProcessing
 {
@@ -19,94 +52,334 @@ pub struct SignalProcessing
 	fft_plan: R2CPlan32,
 
 	fft_absolute: Vec<f32>,
+
+	// left and right channel, only present if the channels are analyzed separately
+	channel_input: Vec<AlignedVec<f32>>,
+	channel_absolute: Vec<Vec<f32>>,
+
+	// only present for multi-resolution analysis
+	bass: Option<BassFft>,
+
+	// number of samples imported per block, including those only used by the bass FFT
+	analysis_len: usize,
+
+	stereo_width: f32,
+
+	// level of the current block in dBFS
+	rms_level:  f32,
+	peak_level: f32,
+
+	// seconds between two blocks
+	update_period: f32,
+
+	// full scale of the imported samples and magnitude of a full scale sine in the spectrum
+	input_full_scale:    f32,
+	spectrum_full_scale: f32,
+
+	silence: SilenceDetector,
+	onset:   OnsetDetector,
+	tempo:   TempoTracker,
+
+	filterbank: Filterbank,
 }
 
 impl SignalProcessing
 {
-	fn hann_window(block_size: usize) -> Vec<f32>
+	pub fn new(config: &Config) -> fftw::error::Result<SignalProcessing>
 	{
-		let mut window = vec![0.0; block_size];
-
-		for i in 0..block_size {
-			window[i] = (PI * (i as f32) / (block_size as f32)).sin().powi(2);
-		}
-
-		window
-	}
+		let block_size = config.block_len;
+		let samp_rate = config.samp_rate;
 
-	pub fn new(block_size: usize, samp_rate: f32) -> fftw::error::Result<SignalProcessing>
-	{
 		let freq_domain_size = block_size/2 + 1;
+		let update_period = config.samples_per_update() as f32 / samp_rate;
+		let num_channels = if config.stereo_mode == StereoMode::Separate { 2 } else { 0 };
+
+		let bass = match config.bass_block_len {
+			Some(bass_size) => Some(BassFft {
+				crossover: config.bass_crossover,
+				window:    window::create(config.window, bass_size, config.kaiser_beta),
+				input:     AlignedVec::new(bass_size),
+				output:    AlignedVec::new(bass_size/2 + 1),
+				plan:      R2CPlan::aligned(&[bass_size], Flag::MEASURE)?,
+				absolute:  vec![0.0; bass_size/2 + 1],
+			}),
+			None => None,
+		};
 
 		let s = SignalProcessing {
 			samp_rate: samp_rate,
-			fft_window: SignalProcessing::hann_window(block_size),
+			fft_window: window::create(config.window, block_size, config.kaiser_beta),
 			fft_input:  AlignedVec::new(block_size),
 			fft_output: AlignedVec::new(freq_domain_size),
 			fft_plan:   R2CPlan::aligned(&[block_size], Flag::MEASURE)?,
 
 			fft_absolute: vec![0.0; freq_domain_size],
+
+			channel_input: (0..num_channels).map(|_| AlignedVec::new(block_size)).collect(),
+			channel_absolute: vec![vec![0.0; freq_domain_size]; num_channels],
+
+			bass,
+
+			analysis_len: config.analysis_len(),
+
+			stereo_width: 0.0,
+
+			rms_level:  silence::MIN_LEVEL,
+			peak_level: silence::MIN_LEVEL,
+
+			update_period,
+
+			input_full_scale:    I16_FULL_SCALE,
+			spectrum_full_scale: 1.0,
+
+			silence: SilenceDetector::new(&config.silence),
+			onset:   OnsetDetector::new(block_size, samp_rate, update_period),
+			tempo:   TempoTracker::new(update_period),
+
+			filterbank: Filterbank::new(&config.filterbank, block_size, samp_rate,
+			                            config.bass_block_len.map(|b| (b, config.bass_crossover))),
 		};
 
 		Ok(s)
 	}
 
+	/// Common processing after a block was imported. `full_scale` is the value of a full scale
+	/// sample in the imported data.
+	fn finish_import(&mut self, full_scale: f32)
+	{
+		self.input_full_scale = full_scale;
+
+		self.measure_level();
+
+		// onsets and beats are only detected again with the next spectrum
+		self.onset.clear();
+		self.tempo.clear();
+
+		self.apply_window();
+	}
+
+	/// Measure the level of the (not yet windowed) input and update the silence detection. The
+	/// DC offset is removed first, so it does not count as signal.
+	fn measure_level(&mut self)
+	{
+		let full_scale = self.input_full_scale;
+		let n = self.fft_input.len() as f32;
+		let mean = self.fft_input.iter().sum::<f32>() / n;
+
+		let (sum_sq, peak) = self.fft_input.iter()
+		                                   .map(|s| s - mean)
+		                                   .fold((0.0, 0.0f32), |(sum_sq, peak), s| (sum_sq + s * s, peak.max(s.abs())));
+
+		self.rms_level = silence::to_dbfs((sum_sq / n).sqrt() / full_scale);
+		self.peak_level = silence::to_dbfs(peak / full_scale);
+
+		let level = self.silence.level(self.rms_level, self.peak_level);
+		self.silence.update(level, self.update_period);
+	}
+
 	fn apply_window(&mut self)
 	{
 		self.fft_input.iter_mut()
 		              .zip(self.fft_window.iter())
 		              .for_each(|(s, w)| *s *= w);
+
+		for input in self.channel_input.iter_mut() {
+			input.iter_mut()
+			     .zip(self.fft_window.iter())
+			     .for_each(|(s, w)| *s *= w);
+		}
+
+		if let Some(bass) = &mut self.bass {
+			bass.input.iter_mut()
+			          .zip(bass.window.iter())
+			          .for_each(|(s, w)| *s *= w);
+		}
+	}
+
+	/// Store the sample with the given index in the imported block. The main FFT only uses the
+	/// last block_len samples, the bass FFT all of them.
+	fn store_sample(&mut self, index: usize, sample: f32)
+	{
+		if let Some(bass) = &mut self.bass {
+			bass.input[index] = sample;
+		}
+
+		if let Some(i) = index.checked_sub(self.analysis_len - self.fft_input.len()) {
+			self.fft_input[i] = sample;
+		}
 	}
 
 	pub fn import_i16_stereo(&mut self, data: &[i16]) -> std::result::Result<(), &str>
 	{
-		if data.len() != 2*self.fft_input.len() {
-			return Err("Stereo data length does not match 2x the FFT input length.");
+		if data.len() != 2*self.analysis_len {
+			return Err("Stereo data length does not match 2x the analysis length.");
 		}
 
 		data.chunks_exact(2)
 			.map(|channels| (channels[0] as f32 + channels[1] as f32) / 2.0 / 32768.0)
-			.zip(self.fft_input.iter_mut())
-			.for_each(|(c, t)| *t = c);
+			.enumerate()
+			.for_each(|(i, c)| self.store_sample(i, c));
 
-		self.apply_window();
+		self.finish_import(1.0);
 
 		Ok(())
 	}
 
 	pub fn import_i16_mono(&mut self, data: &[i16]) -> std::result::Result<(), &str>
 	{
-		if data.len() != self.fft_input.len() {
-			return Err("Mono data length does not match the FFT input length.");
+		if data.len() != self.analysis_len {
+			return Err("Mono data length does not match the analysis length.");
 		}
 
 		data.iter()
 			.map(|&sample| (sample as f32) / 32768.0)
-			.zip(self.fft_input.iter_mut())
-			.for_each(|(c, t)| *t = c);
+			.enumerate()
+			.for_each(|(i, c)| self.store_sample(i, c));
 
-		self.apply_window();
+		self.finish_import(1.0);
 
 		Ok(())
 	}
 
-	pub fn import_i16_mono_from_iter<'a>(&mut self, mut iter: impl std::iter::Iterator<Item=&'a i16>) -> std::result::Result<(), &str>
+	/// Import stereo frames. The channels are mixed down for the main spectrum and, if enabled,
+	/// also kept separately.
+	pub fn import_i16_stereo_from_iter<'a>(&mut self, mut iter: impl std::iter::Iterator<Item=&'a [i16; 2]>) -> std::result::Result<(), &str>
 	{
-		for fft_samp in self.fft_input.iter_mut() {
-			match iter.next() {
-				Some(sample) => *fft_samp = *sample as f32,
-				None         => return Err("Too few samples in input.")
+		// for the correlation of both channels
+		let (mut ll, mut rr, mut lr) = (0.0f32, 0.0f32, 0.0f32);
+
+		// samples before the main block are only used by the bass FFT
+		let offset = self.analysis_len - self.fft_input.len();
+
+		for j in 0 .. self.analysis_len {
+			let [left, right] = match iter.next() {
+				Some(frame) => *frame,
+				None        => return Err("Too few samples in input.")
+			};
+
+			self.store_sample(j, (left as f32 + right as f32) / 2.0);
+
+			let i = match j.checked_sub(offset) {
+				Some(i) => i,
+				None    => continue,
+			};
+
+			ll += left as f32 * left as f32;
+			rr += right as f32 * right as f32;
+			lr += left as f32 * right as f32;
+
+			if let [l, r] = &mut self.channel_input[..] {
+				l[i] = left as f32;
+				r[i] = right as f32;
 			}
 		}
 
-		self.apply_window();
+		self.stereo_width = match ll * rr {
+			p if p > 0.0 => (1.0 - lr / p.sqrt()) / 2.0,
+			_            => 0.0,
+		};
+
+		self.finish_import(I16_FULL_SCALE);
 
 		Ok(())
 	}
 
+	pub fn has_separate_channels(&self) -> bool
+	{
+		!self.channel_input.is_empty()
+	}
+
+	/// Stereo width of the current block: 0 for mono, 0.5 for uncorrelated channels and 1 for
+	/// channels with opposite phase.
+	pub fn get_stereo_width(&self) -> f32
+	{
+		self.stereo_width
+	}
+
+	/// RMS level of the current block in dBFS.
+	pub fn get_rms_level(&self) -> f32
+	{
+		self.rms_level
+	}
+
+	/// Peak level of the current block in dBFS.
+	pub fn get_peak_level(&self) -> f32
+	{
+		self.peak_level
+	}
+
+	/// Whether the input has been silent for at least the configured hold time.
 	pub fn is_silent(&self) -> bool
 	{
-		return self.fft_input.iter().sum::<f32>() == 0.0;
+		self.silence.is_silent()
+	}
+
+	/// Duration of the current silence gap in seconds, counted from the moment the level fell
+	/// below the threshold (before the hold time elapsed). 0 while the input is not quiet.
+	pub fn get_silence_duration(&self) -> f32
+	{
+		self.silence.quiet_time()
+	}
+
+	/// Onset strength of the current spectrum: the mean increase of the log-compressed magnitudes
+	/// since the previous one.
+	pub fn get_onset_strength(&self) -> f32
+	{
+		self.onset.strength()
+	}
+
+	/// Onset strength in the frequency band of kick, snare or hi-hat.
+	pub fn get_band_onset_strength(&self, band: OnsetBand) -> f32
+	{
+		self.onset.band_strength(band)
+	}
+
+	/// Whether an onset was detected in the given band in the current spectrum.
+	pub fn is_onset(&self, band: OnsetBand) -> bool
+	{
+		self.onset.is_onset(band)
+	}
+
+	/// Whether a beat was detected in the current spectrum.
+	pub fn is_beat(&self) -> bool
+	{
+		self.onset.is_beat()
+	}
+
+	/// Number of bands of the filterbank.
+	pub fn get_num_bands(&self) -> usize
+	{
+		self.filterbank.num_bands()
+	}
+
+	/// Level of a filterbank band in dB relative to a full scale sine.
+	pub fn get_band_level(&self, band: usize) -> f32
+	{
+		self.filterbank.level(band)
+	}
+
+	/// Estimated tempo in BPM, or 0 if it is not known yet.
+	pub fn get_tempo(&self) -> f32
+	{
+		self.tempo.bpm()
+	}
+
+	/// Confidence of the tempo estimate, from 0 (none) to 1 (strictly periodic onsets).
+	pub fn get_tempo_confidence(&self) -> f32
+	{
+		self.tempo.confidence()
+	}
+
+	/// Position within the current beat, from 0 (on the beat) to 1 (just before the next beat).
+	pub fn get_beat_phase(&self) -> f32
+	{
+		self.tempo.phase()
+	}
+
+	/// Whether the tracked beat is the first beat of a bar.
+	pub fn is_downbeat(&self) -> bool
+	{
+		self.tempo.is_downbeat()
 	}
 
 	pub fn update_fft(&mut self) -> fftw::error::Result<()>
@@ -117,20 +390,104 @@ impl SignalProcessing
 			*abs_sample = self.fft_output[i].norm();
 		}
 
+		for (input, absolute) in self.channel_input.iter_mut().zip(self.channel_absolute.iter_mut()) {
+			self.fft_plan.r2c(input, &mut self.fft_output)?;
+
+			for (i, abs_sample) in absolute.iter_mut().enumerate() {
+				*abs_sample = self.fft_output[i].norm();
+			}
+		}
+
+		// a full scale sine results in a peak of half the sum of the window
+		let window_sum: f32 = self.fft_window.iter().sum();
+		self.spectrum_full_scale = self.input_full_scale * window_sum / 2.0;
+
+		if let Some(bass) = &mut self.bass {
+			bass.plan.r2c(&mut bass.input, &mut bass.output)?;
+
+			let scale = window_sum / bass.window.iter().sum::<f32>();
+
+			for (i, abs_sample) in bass.absolute.iter_mut().enumerate() {
+				*abs_sample = bass.output[i].norm() * scale;
+			}
+		}
+
+		self.onset.update(&self.fft_absolute, self.spectrum_full_scale);
+
+		self.filterbank.update(&self.fft_absolute, self.bass.as_ref().map(|b| &b.absolute[..]), self.spectrum_full_scale);
+
+		// the onset envelope for the tempo emphasizes the kick drum
+		self.tempo.update(self.onset.strength() + self.onset.band_strength(OnsetBand::Kick));
+
 		Ok(())
 	}
 
-	fn freq_to_idx(&self, freq: f32) -> usize
+	/// FFT bin of a frequency in a spectrum of the given block size.
+	fn freq_to_idx(&self, freq: f32, block_size: usize) -> usize
 	{
-		(freq * (self.fft_input.len() as f32) / self.samp_rate) as usize
+		((freq * (block_size as f32) / self.samp_rate) as usize).min(block_size / 2)
 	}
 
-	pub fn get_energy_in_band(&self, freq_start: f32, freq_end: f32) -> f32
+	/// Mean magnitude in a band of a spectrum.
+	fn band_energy(&self, spectrum: &[f32], freq_start: f32, freq_end: f32) -> f32
 	{
-		let start_bin = self.freq_to_idx(freq_start);
-		let end_bin = self.freq_to_idx(freq_end);
+		let block_size = 2 * (spectrum.len() - 1);
+		let start_bin = self.freq_to_idx(freq_start, block_size);
+		let end_bin = self.freq_to_idx(freq_end, block_size);
 
-		let sum: f32 = self.fft_absolute[start_bin ..= end_bin].iter().sum();
+		let sum: f32 = spectrum[start_bin ..= end_bin].iter().sum();
 		sum / (end_bin - start_bin + 1) as f32
 	}
+
+	/// Energy in a band of the mixed signal. With a bass FFT, the part of the band below the
+	/// crossover frequency is taken from its spectrum and both parts are weighted by their width.
+	pub fn get_energy_in_band(&self, freq_start: f32, freq_end: f32) -> f32
+	{
+		let bass = match &self.bass {
+			Some(bass) if freq_start < bass.crossover => bass,
+			_ => return self.band_energy(&self.fft_absolute, freq_start, freq_end),
+		};
+
+		if freq_end <= bass.crossover {
+			return self.band_energy(&bass.absolute, freq_start, freq_end);
+		}
+
+		let low = self.band_energy(&bass.absolute, freq_start, bass.crossover);
+		let high = self.band_energy(&self.fft_absolute, bass.crossover, freq_end);
+
+		(low * (bass.crossover - freq_start) + high * (freq_end - bass.crossover)) / (freq_end - freq_start)
+	}
+
+	/// Level of the energy in a band in dB relative to a full scale sine.
+	pub fn get_level_in_band(&self, freq_start: f32, freq_end: f32) -> f32
+	{
+		silence::to_dbfs(self.get_energy_in_band(freq_start, freq_end) / self.spectrum_full_scale)
+	}
+
+	/// Energy in a band of a single channel (0 = left, 1 = right). Without separate channels, the
+	/// energy of the mixed signal is returned.
+	pub fn get_channel_energy_in_band(&self, channel: usize, freq_start: f32, freq_end: f32) -> f32
+	{
+		match self.channel_absolute.get(channel) {
+			Some(spectrum) => self.band_energy(spectrum, freq_start, freq_end),
+			None           => self.get_energy_in_band(freq_start, freq_end),
+		}
+	}
+
+	/// Balance of the energy in a band between the channels, from -1 (only left) to +1 (only
+	/// right). Without separate channels, the balance is always 0.
+	pub fn get_balance_in_band(&self, freq_start: f32, freq_end: f32) -> f32
+	{
+		if !self.has_separate_channels() {
+			return 0.0;
+		}
+
+		let left = self.get_channel_energy_in_band(0, freq_start, freq_end);
+		let right = self.get_channel_energy_in_band(1, freq_start, freq_end);
+
+		match left + right {
+			sum if sum > 0.0 => (right - left) / sum,
+			_                => 0.0,
+		}
+	}
 }
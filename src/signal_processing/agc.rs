@@ -0,0 +1,167 @@
+// vim: noet
+
+use std::collections::VecDeque;
+
+use crate::config::AgcConfig;
+use crate::signal_processing::silence::MIN_LEVEL;
+
+// the levels are collected in a histogram from MIN_LEVEL to MAX_LEVEL dB in steps of
+// HISTOGRAM_STEP dB
+const MAX_LEVEL: f32      = 20.0;
+const HISTOGRAM_STEP: f32 = 0.5;
+
+// seconds of past levels from which the percentiles are taken
+const HISTORY_WINDOW: f32 = 10.0;
+
+// percentile of the recent levels taken as the noise floor
+const NOISE_PERCENTILE: f32 = 0.1;
+
+// minimum distance between the noise floor and the reference level in dB
+const MIN_RANGE: f32 = 6.0;
+
+/// Names of the AGC parameters that can be changed at runtime, see `Agc::set_parameter()`.
+pub const PARAMETERS: [&str; 5] = ["agc_attack", "agc_release", "agc_percentile", "agc_floor", "agc_ceiling"];
+
+/// Amplitude ratio of a level in dB.
+fn to_amplitude(level: f32) -> f32
+{
+	10.0f32.powf(level / 20.0)
+}
+
+/// Move `value` towards `target` with a first-order lowpass of the given time constant.
+fn follow(value: f32, target: f32, time_constant: f32, dt: f32) -> f32
+{
+	match time_constant {
+		t if t > 0.0 => value + (1.0 - (-dt / t).exp()) * (target - value),
+		_            => target,
+	}
+}
+
+/*
+ * Automatic gain control for one band level. The distribution of the levels of the last
+ * HISTORY_WINDOW seconds is kept in a histogram. Its configured percentile is the reference level,
+ * which is normalized to 1, and a low percentile estimates the noise floor, which is normalized
+ * to 0. Both follow their percentiles with the attack time constant when the signal gets louder
+ * and with the release time constant when it gets quieter. The floor and ceiling settings limit
+ * the gain, so noise in quiet passages is not amplified to full brightness.
+ */
+pub struct Agc
+{
+	config: AgcConfig,
+
+	// seconds between two updates
+	dt: f32,
+
+	// histogram bins of the recent levels and the number of levels per bin
+	history:     VecDeque<usize>,
+	history_len: usize,
+	histogram:   Vec<u32>,
+
+	// tracked levels in dB, None before the first update
+	tracked: Option<(f32, f32)>,
+}
+
+impl Agc
+{
+	pub fn new(config: &AgcConfig, update_period: f32) -> Agc
+	{
+		let history_len = ((HISTORY_WINDOW / update_period).ceil() as usize).max(1);
+		let num_bins = ((MAX_LEVEL - MIN_LEVEL) / HISTOGRAM_STEP) as usize + 1;
+
+		Agc {
+			config: *config,
+			dt: update_period,
+			history: VecDeque::with_capacity(history_len),
+			history_len,
+			histogram: vec![0; num_bins],
+			tracked: None,
+		}
+	}
+
+	/// Level at the given percentile of the recent levels.
+	fn percentile(&self, percentile: f32) -> f32
+	{
+		let rank = ((percentile * self.history.len() as f32).ceil() as u32).max(1);
+		let mut count = 0;
+
+		for (bin, n) in self.histogram.iter().enumerate() {
+			count += n;
+
+			if count >= rank {
+				return MIN_LEVEL + bin as f32 * HISTOGRAM_STEP;
+			}
+		}
+
+		MAX_LEVEL
+	}
+
+	/// Add a new level in dB and return it normalized to 0..1.
+	pub fn update(&mut self, level: f32) -> f32
+	{
+		if self.history.len() == self.history_len {
+			if let Some(bin) = self.history.pop_front() {
+				self.histogram[bin] -= 1;
+			}
+		}
+
+		let bin = ((level.clamp(MIN_LEVEL, MAX_LEVEL) - MIN_LEVEL) / HISTOGRAM_STEP).round() as usize;
+		self.history.push_back(bin);
+		self.histogram[bin] += 1;
+
+		let reference = self.percentile(self.config.percentile);
+		let noise_floor = self.percentile(NOISE_PERCENTILE);
+
+		let (attack, release, dt) = (self.config.attack, self.config.release, self.dt);
+
+		self.tracked = Some(match self.tracked {
+			None => (reference, noise_floor),
+
+			// the reference rises with the attack and the noise floor with the release time
+			Some((r, n)) => (
+				follow(r, reference, if reference > r { attack } else { release }, dt),
+				follow(n, noise_floor, if noise_floor > n { release } else { attack }, dt),
+			),
+		});
+
+		self.normalize(level)
+	}
+
+	/// Reference level in dB that is normalized to 1, limited by floor and ceiling.
+	pub fn reference(&self) -> f32
+	{
+		let reference = self.tracked.map_or(self.config.ceiling, |(r, _)| r);
+
+		reference.max(self.config.floor + MIN_RANGE).min(self.config.ceiling)
+	}
+
+	/// Estimated noise floor in dB that is normalized to 0, but at least the configured floor.
+	pub fn noise_floor(&self) -> f32
+	{
+		let noise_floor = self.tracked.map_or(self.config.floor, |(_, n)| n);
+
+		noise_floor.max(self.config.floor).min(self.reference() - MIN_RANGE)
+	}
+
+	/// Normalize a level in dB with the current reference level and noise floor, without
+	/// updating them. The result is linear in amplitude and limited to 0..1.
+	pub fn normalize(&self, level: f32) -> f32
+	{
+		let lower = to_amplitude(self.noise_floor());
+		let upper = to_amplitude(self.reference());
+
+		((to_amplitude(level) - lower) / (upper - lower)).clamp(0.0, 1.0)
+	}
+
+	/// Change a setting by its parameter name (see PARAMETERS). Unknown names are ignored.
+	pub fn set_parameter(&mut self, name: &str, value: f32)
+	{
+		match name {
+			"agc_attack"     => self.config.attack = value.max(0.0),
+			"agc_release"    => self.config.release = value.max(0.0),
+			"agc_percentile" => self.config.percentile = value.clamp(0.0, 1.0),
+			"agc_floor"      => self.config.floor = value,
+			"agc_ceiling"    => self.config.ceiling = value,
+			_ => (),
+		}
+	}
+}
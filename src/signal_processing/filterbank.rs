@@ -0,0 +1,155 @@
+// vim: noet
+
+use crate::config::{FilterbankConfig, FrequencyScale};
+use crate::signal_processing::silence::MIN_LEVEL;
+
+/// Convert a frequency in Hz to the given scale.
+fn to_scale(scale: FrequencyScale, freq: f32) -> f32
+{
+	match scale {
+		FrequencyScale::Log  => freq.log2(),
+		FrequencyScale::Mel  => 2595.0 * (1.0 + freq / 700.0).log10(),
+		FrequencyScale::Bark => 26.81 * freq / (1960.0 + freq) - 0.53,
+	}
+}
+
+/// Convert a value on the given scale back to a frequency in Hz.
+fn from_scale(scale: FrequencyScale, value: f32) -> f32
+{
+	match scale {
+		FrequencyScale::Log  => value.exp2(),
+		FrequencyScale::Mel  => 700.0 * (10.0f32.powf(value / 2595.0) - 1.0),
+		FrequencyScale::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+	}
+}
+
+/*
+ * One triangular filter: the weights of the FFT bins starting at `start`, normalized to a sum
+ * of 1. Filters of the bass bands apply to the spectrum of the longer bass FFT.
+ */
+struct Filter
+{
+	bass:    bool,
+	start:   usize,
+	weights: Vec<f32>,
+}
+
+impl Filter
+{
+	fn new(lower: f32, center: f32, upper: f32, block_size: usize, samp_rate: f32, bass: bool) -> Filter
+	{
+		let num_bins = block_size / 2 + 1;
+		let bin_width = samp_rate / block_size as f32;
+
+		let start = ((lower / bin_width).floor() as usize).min(num_bins - 1);
+		let end = ((upper / bin_width).ceil() as usize).min(num_bins - 1);
+
+		let mut weights: Vec<f32> = (start ..= end).map(|k| {
+		                                              let freq = k as f32 * bin_width;
+
+		                                              if freq <= lower || freq >= upper {
+		                                                  0.0
+		                                              } else if freq <= center {
+		                                                  (freq - lower) / (center - lower)
+		                                              } else {
+		                                                  (upper - freq) / (upper - center)
+		                                              }
+		                                          })
+		                                          .collect();
+
+		let mut sum: f32 = weights.iter().sum();
+
+		// no bin inside the filter: interpolate at the center
+		if sum <= 0.0 {
+			let pos = center / bin_width;
+			let below = (pos.floor() as usize).clamp(start, end);
+			let above = (below + 1).min(end);
+			let frac = pos - pos.floor();
+
+			weights[below - start] += 1.0 - frac;
+			weights[above - start] += frac;
+
+			sum = 1.0;
+		}
+
+		weights.iter_mut().for_each(|w| *w /= sum);
+
+		Filter { bass, start, weights }
+	}
+}
+
+/*
+ * A bank of overlapping triangular filters evenly spaced on a logarithmic, mel or Bark scale.
+ * Each filter reaches from the center of the previous to the center of the next band. Filters
+ * narrower than the FFT resolution interpolate between the two bins nearest to their center. The
+ * band levels are the weighted mean power in dB relative to a full scale sine.
+ *
+ * With a bass FFT, the bands centered below the crossover frequency use its finer resolution.
+ */
+pub struct Filterbank
+{
+	filters: Vec<Filter>,
+	levels:  Vec<f32>,
+}
+
+impl Filterbank
+{
+	/// `bass` is the block size of the bass FFT and the crossover frequency, if enabled.
+	pub fn new(config: &FilterbankConfig, block_size: usize, samp_rate: f32, bass: Option<(usize, f32)>) -> Filterbank
+	{
+		let max_freq = config.max_freq.min(samp_rate / 2.0);
+		let min_freq = config.min_freq.min(max_freq / 2.0);
+
+		let (scale_min, scale_max) = (to_scale(config.scale, min_freq), to_scale(config.scale, max_freq));
+		let step = (scale_max - scale_min) / (config.bands + 1) as f32;
+
+		let edges: Vec<f32> = (0 ..= config.bands + 1).map(|i| from_scale(config.scale, scale_min + i as f32 * step))
+		                                             .collect();
+
+		let filters = edges.windows(3)
+		                   .map(|e| match bass {
+		                       Some((bass_size, crossover)) if e[1] < crossover => Filter::new(e[0], e[1], e[2], bass_size, samp_rate, true),
+		                       _                                                 => Filter::new(e[0], e[1], e[2], block_size, samp_rate, false),
+		                   })
+		                   .collect();
+
+		Filterbank {
+			filters,
+			levels: vec![MIN_LEVEL; config.bands],
+		}
+	}
+
+	/// Calculate the band levels from a magnitude spectrum and, if enabled, the spectrum of the
+	/// bass FFT. `full_scale` is the magnitude of a full scale sine in both.
+	pub fn update(&mut self, spectrum: &[f32], bass_spectrum: Option<&[f32]>, full_scale: f32)
+	{
+		let full_scale_power = full_scale * full_scale;
+
+		for (filter, level) in self.filters.iter().zip(self.levels.iter_mut()) {
+			let spectrum = match bass_spectrum {
+				Some(bass) if filter.bass => bass,
+				_                         => spectrum,
+			};
+
+			let power: f32 = spectrum[filter.start ..].iter()
+			                                          .zip(filter.weights.iter())
+			                                          .map(|(m, w)| w * m * m)
+			                                          .sum();
+
+			*level = match power {
+				p if p > 0.0 => (10.0 * (p / full_scale_power).log10()).max(MIN_LEVEL),
+				_            => MIN_LEVEL,
+			};
+		}
+	}
+
+	pub fn num_bands(&self) -> usize
+	{
+		self.levels.len()
+	}
+
+	pub fn level(&self, band: usize) -> f32
+	{
+		self.levels[band]
+	}
+}
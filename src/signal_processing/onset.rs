@@ -0,0 +1,215 @@
+// vim: noet
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+// magnitudes are compressed with ln(1 + COMPRESSION * magnitude) before the flux is calculated
+const COMPRESSION: f32 = 100.0;
+
+// the adaptive threshold is the mean flux of the last THRESHOLD_WINDOW seconds plus
+// THRESHOLD_FACTOR standard deviations plus THRESHOLD_OFFSET
+const THRESHOLD_WINDOW: f32 = 0.5;
+const THRESHOLD_FACTOR: f32 = 2.0;
+const THRESHOLD_OFFSET: f32 = 0.02;
+
+// minimum time between two onsets in one band and between two beats, in seconds
+const ONSET_HOLDOFF: f32 = 0.08;
+const BEAT_HOLDOFF: f32  = 0.2;
+
+/// Frequency bands with separate onset detection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OnsetBand
+{
+	Kick,
+	Snare,
+	HiHat,
+}
+
+impl OnsetBand
+{
+	pub const ALL: [OnsetBand; 3] = [OnsetBand::Kick, OnsetBand::Snare, OnsetBand::HiHat];
+
+	/// Frequency range of the band in Hz.
+	pub fn frequencies(self) -> (f32, f32)
+	{
+		match self {
+			OnsetBand::Kick  => (   30.0,   150.0),
+			OnsetBand::Snare => (  150.0,  2500.0),
+			OnsetBand::HiHat => ( 6000.0, 16000.0),
+		}
+	}
+}
+
+/*
+ * Spectral flux in a range of FFT bins with an adaptive threshold.
+ */
+struct BandFlux
+{
+	bins: Range<usize>,
+
+	flux:        f32,
+	history:     VecDeque<f32>,
+	history_len: usize,
+
+	onset:   bool,
+	holdoff: f32,
+}
+
+impl BandFlux
+{
+	fn new(bins: Range<usize>, history_len: usize) -> BandFlux
+	{
+		BandFlux {
+			bins,
+			flux: 0.0,
+			history: VecDeque::with_capacity(history_len),
+			history_len,
+			onset: false,
+			holdoff: 0.0,
+		}
+	}
+
+	fn threshold(&self) -> f32
+	{
+		if self.history.is_empty() {
+			return THRESHOLD_OFFSET;
+		}
+
+		let n = self.history.len() as f32;
+		let mean = self.history.iter().sum::<f32>() / n;
+		let variance = self.history.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / n;
+
+		mean + THRESHOLD_FACTOR * variance.sqrt() + THRESHOLD_OFFSET
+	}
+
+	fn update(&mut self, diff: &[f32], dt: f32)
+	{
+		let bins = &diff[self.bins.clone()];
+
+		self.flux = match bins.len() {
+			0 => 0.0,
+			n => bins.iter().sum::<f32>() / n as f32,
+		};
+
+		self.holdoff -= dt;
+		self.onset = self.holdoff <= 0.0 && self.flux > self.threshold();
+
+		if self.onset {
+			self.holdoff = ONSET_HOLDOFF;
+		}
+
+		if self.history.len() == self.history_len {
+			self.history.pop_front();
+		}
+
+		self.history.push_back(self.flux);
+	}
+}
+
+/*
+ * Onset detection based on the spectral flux: the increase of the log-compressed magnitudes
+ * between two consecutive spectra. An onset is detected when the flux exceeds an adaptive threshold
+ * derived from its recent mean and standard deviation, which adapts to the loudness and the
+ * density of the music. A beat is a kick onset, at most one every BEAT_HOLDOFF seconds.
+ */
+pub struct OnsetDetector
+{
+	previous: Vec<f32>,
+	diff:     Vec<f32>,
+
+	full:  BandFlux,
+	bands: Vec<BandFlux>,
+
+	beat:         bool,
+	beat_holdoff: f32,
+
+	// seconds between two updates
+	dt: f32,
+}
+
+impl OnsetDetector
+{
+	pub fn new(block_size: usize, samp_rate: f32, update_period: f32) -> OnsetDetector
+	{
+		let num_bins = block_size / 2 + 1;
+		let history_len = ((THRESHOLD_WINDOW / update_period).ceil() as usize).max(1);
+
+		let to_bin = |freq: f32| ((freq * block_size as f32 / samp_rate) as usize).min(num_bins - 1);
+
+		let bands = OnsetBand::ALL.iter()
+		                          .map(|b| {
+		                              let (start, end) = b.frequencies();
+		                              BandFlux::new(to_bin(start) .. to_bin(end) + 1, history_len)
+		                          })
+		                          .collect();
+
+		OnsetDetector {
+			previous: vec![0.0; num_bins],
+			diff: vec![0.0; num_bins],
+			// skip the DC bin
+			full: BandFlux::new(1 .. num_bins, history_len),
+			bands,
+			beat: false,
+			beat_holdoff: 0.0,
+			dt: update_period,
+		}
+	}
+
+	/// Process a new magnitude spectrum. `full_scale` is the magnitude of a full scale sine.
+	pub fn update(&mut self, spectrum: &[f32], full_scale: f32)
+	{
+		for ((&magnitude, previous), diff) in spectrum.iter().zip(self.previous.iter_mut()).zip(self.diff.iter_mut()) {
+			let compressed = (COMPRESSION * magnitude / full_scale).ln_1p();
+
+			*diff = (compressed - *previous).max(0.0);
+			*previous = compressed;
+		}
+
+		self.full.update(&self.diff, self.dt);
+
+		for band in self.bands.iter_mut() {
+			band.update(&self.diff, self.dt);
+		}
+
+		self.beat_holdoff -= self.dt;
+		self.beat = self.beat_holdoff <= 0.0 && self.is_onset(OnsetBand::Kick);
+
+		if self.beat {
+			self.beat_holdoff = BEAT_HOLDOFF;
+		}
+	}
+
+	/// Forget the onsets of the last update, e.g. if no new spectrum is calculated.
+	pub fn clear(&mut self)
+	{
+		self.full.onset = false;
+		self.full.flux = 0.0;
+
+		for band in self.bands.iter_mut() {
+			band.onset = false;
+			band.flux = 0.0;
+		}
+
+		self.beat = false;
+	}
+
+	pub fn strength(&self) -> f32
+	{
+		self.full.flux
+	}
+
+	pub fn band_strength(&self, band: OnsetBand) -> f32
+	{
+		self.bands[band as usize].flux
+	}
+
+	pub fn is_onset(&self, band: OnsetBand) -> bool
+	{
+		self.bands[band as usize].onset
+	}
+
+	pub fn is_beat(&self) -> bool
+	{
+		self.beat
+	}
+}
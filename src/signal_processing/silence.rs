@@ -0,0 +1,92 @@
+// vim: noet
+
+use crate::config::{LevelDetector, SilenceConfig};
+
+// lowest reported level in dBFS, returned for digital silence
+pub const MIN_LEVEL: f32 = -120.0;
+
+/// Convert an amplitude relative to full scale to dBFS.
+pub fn to_dbfs(amplitude: f32) -> f32
+{
+	if amplitude > 0.0 {
+		(20.0 * amplitude.log10()).max(MIN_LEVEL)
+	} else {
+		MIN_LEVEL
+	}
+}
+
+/*
+ * Detects silence from the input level with hysteresis: the input becomes quiet when the level
+ * falls below the enter threshold and counts as silent once it stayed quiet for the enter hold
+ * time. It only becomes loud again after the level exceeded the exit threshold for the exit hold
+ * time, so short clicks do not end a silence gap.
+ */
+pub struct SilenceDetector
+{
+	config: SilenceConfig,
+
+	quiet:      bool,
+	quiet_time: f32,
+	loud_time:  f32,
+}
+
+impl SilenceDetector
+{
+	pub fn new(config: &SilenceConfig) -> SilenceDetector
+	{
+		SilenceDetector {
+			config: config.clone(),
+			quiet: false,
+			quiet_time: 0.0,
+			loud_time: 0.0,
+		}
+	}
+
+	/// Select the configured level from the RMS and the peak level (both in dBFS).
+	pub fn level(&self, rms: f32, peak: f32) -> f32
+	{
+		match self.config.detector {
+			LevelDetector::Rms  => rms,
+			LevelDetector::Peak => peak,
+		}
+	}
+
+	/// Update the state with the level (in dBFS) of a block that started `dt` seconds after the
+	/// previous one.
+	pub fn update(&mut self, level: f32, dt: f32)
+	{
+		if !self.quiet {
+			if level < self.config.enter_threshold {
+				self.quiet = true;
+				self.quiet_time = 0.0;
+				self.loud_time = 0.0;
+			}
+
+			return;
+		}
+
+		self.quiet_time += dt;
+
+		if level > self.config.exit_threshold {
+			self.loud_time += dt;
+
+			if self.loud_time >= self.config.exit_hold {
+				self.quiet = false;
+				self.quiet_time = 0.0;
+			}
+		} else {
+			self.loud_time = 0.0;
+		}
+	}
+
+	/// Time in seconds since the input became quiet, or 0 if it is not quiet.
+	pub fn quiet_time(&self) -> f32
+	{
+		self.quiet_time
+	}
+
+	pub fn is_silent(&self) -> bool
+	{
+		self.quiet && self.quiet_time >= self.config.enter_hold
+	}
+}
@@ -0,0 +1,268 @@
+// vim: noet
+
+use std::collections::VecDeque;
+
+// length of the onset envelope used for the tempo estimation, in seconds
+const ENVELOPE_WINDOW: f32 = 6.0;
+
+// the tempo is estimated again every this many seconds
+const ESTIMATION_INTERVAL: f32 = 0.5;
+
+// range of detected tempos in BPM
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+
+/// Longest time between two updates in seconds at which the fastest tempo still spans two updates.
+pub const MAX_UPDATE_PERIOD: f32 = 60.0 / MAX_BPM / 2.0;
+
+// the autocorrelation is weighted with a log-Gaussian around this tempo (in BPM), with a width
+// of PRIOR_WIDTH octaves, to prefer the most likely of several related tempos
+const PRIOR_BPM: f32   = 120.0;
+const PRIOR_WIDTH: f32 = 1.0;
+
+// a new estimate within this relative distance of the current tempo is smoothed with
+// TEMPO_ALPHA; a farther one replaces it
+const TEMPO_TOLERANCE: f32 = 0.05;
+const TEMPO_ALPHA: f32     = 0.3;
+
+// number of past beats combined to measure the beat phase, and the fraction of the phase error
+// corrected per estimation
+const PHASE_BEATS: usize = 4;
+const PHASE_GAIN: f32    = 0.5;
+
+// beats per bar and decay of the accents used to find the downbeat (per beat)
+const BEATS_PER_BAR: usize = 4;
+const ACCENT_DECAY: f32    = 0.9;
+
+/// Wrap a phase difference to -0.5 .. 0.5.
+fn wrap_phase(diff: f32) -> f32
+{
+	diff - diff.round()
+}
+
+/*
+ * Estimates the tempo from the autocorrelation of the onset envelope and tracks the beat phase.
+ * The period with the strongest autocorrelation (weighted with a tempo prior) gives the tempo;
+ * its normalized autocorrelation is the confidence. Between estimations, the phase advances with
+ * the tempo. At each estimation, it is pulled towards the offset at which a comb of past beats
+ * matches the envelope best. The downbeat is the beat in the bar with the strongest accents.
+ */
+pub struct TempoTracker
+{
+	envelope:     VecDeque<f32>,
+	envelope_len: usize,
+
+	// seconds between two updates
+	dt: f32,
+
+	// time until the next estimation
+	next_estimation: f32,
+
+	// beat period in updates, 0 if unknown
+	period:     f32,
+	confidence: f32,
+
+	phase: f32,
+
+	// position of the current beat in the bar and the accents of all positions
+	beat_in_bar: usize,
+	accents:     [f32; BEATS_PER_BAR],
+	beat_peak:   f32,
+
+	beat:     bool,
+	downbeat: bool,
+}
+
+impl TempoTracker
+{
+	pub fn new(update_period: f32) -> TempoTracker
+	{
+		let envelope_len = (ENVELOPE_WINDOW / update_period) as usize;
+
+		TempoTracker {
+			envelope: VecDeque::with_capacity(envelope_len),
+			envelope_len,
+			dt: update_period,
+			next_estimation: ESTIMATION_INTERVAL,
+			period: 0.0,
+			confidence: 0.0,
+			phase: 0.0,
+			beat_in_bar: 0,
+			accents: [0.0; BEATS_PER_BAR],
+			beat_peak: 0.0,
+			beat: false,
+			downbeat: false,
+		}
+	}
+
+	/// Add the onset strength of a new spectrum.
+	pub fn update(&mut self, onset_strength: f32)
+	{
+		if self.envelope.len() == self.envelope_len {
+			self.envelope.pop_front();
+		}
+
+		self.envelope.push_back(onset_strength);
+
+		self.next_estimation -= self.dt;
+
+		if self.next_estimation <= 0.0 {
+			self.next_estimation += ESTIMATION_INTERVAL;
+			self.estimate();
+		}
+
+		self.beat = false;
+		self.downbeat = false;
+
+		if self.period <= 0.0 {
+			return;
+		}
+
+		// the strongest onset in the half period around a beat is its accent
+		if wrap_phase(self.phase).abs() < 0.25 {
+			self.beat_peak = self.beat_peak.max(onset_strength);
+		}
+
+		self.phase += 1.0 / self.period;
+
+		if self.phase >= 1.0 {
+			self.phase -= 1.0;
+			self.beat = true;
+		}
+
+		// the accent is complete a quarter period after the beat
+		if self.phase >= 0.25 && self.phase - 1.0 / self.period < 0.25 {
+			let accent = &mut self.accents[self.beat_in_bar];
+			*accent = ACCENT_DECAY * *accent + self.beat_peak;
+			self.beat_peak = 0.0;
+		}
+
+		if self.beat {
+			self.beat_in_bar = (self.beat_in_bar + 1) % BEATS_PER_BAR;
+
+			let strongest = (0 .. BEATS_PER_BAR).max_by(|&a, &b| self.accents[a].total_cmp(&self.accents[b]))
+			                                    .unwrap_or(0);
+
+			self.downbeat = self.beat_in_bar == strongest;
+		}
+	}
+
+	/// Autocorrelation of the mean-free envelope at the given lag.
+	fn autocorrelation(envelope: &[f32], lag: usize) -> f32
+	{
+		envelope.iter().zip(envelope[lag ..].iter()).map(|(a, b)| a * b).sum()
+	}
+
+	fn estimate(&mut self)
+	{
+		// the lags next to the range are needed for the refinement, so the shortest lag is 2
+		let min_lag = ((60.0 / MAX_BPM / self.dt).floor() as usize).max(2);
+		let max_lag = ((60.0 / MIN_BPM / self.dt).ceil() as usize).max(min_lag + 1);
+
+		// at least two periods are needed
+		if self.envelope.len() < 2 * max_lag {
+			return;
+		}
+
+		let mean = self.envelope.iter().sum::<f32>() / self.envelope.len() as f32;
+		let envelope: Vec<f32> = self.envelope.iter().map(|e| e - mean).collect();
+
+		let energy = TempoTracker::autocorrelation(&envelope, 0);
+
+		if energy <= 0.0 {
+			self.confidence = 0.0;
+			return;
+		}
+
+		let acf: Vec<f32> = (min_lag - 1 ..= max_lag + 1).map(|lag| TempoTracker::autocorrelation(&envelope, lag))
+		                                                 .collect();
+
+		let prior = |lag: f32| {
+			let bpm = 60.0 / (lag * self.dt);
+			(-0.5 * ((bpm / PRIOR_BPM).log2() / PRIOR_WIDTH).powi(2)).exp()
+		};
+
+		// acf[i] belongs to the lag min_lag - 1 + i
+		let best = (1 .. acf.len() - 1).max_by(|&a, &b| {
+		                                   let wa = acf[a] * prior((min_lag - 1 + a) as f32);
+		                                   let wb = acf[b] * prior((min_lag - 1 + b) as f32);
+		                                   wa.total_cmp(&wb)
+		                               })
+		                               .unwrap();
+
+		if acf[best] <= 0.0 {
+			self.confidence = 0.0;
+			return;
+		}
+
+		// refine the lag with a parabola through the neighbours
+		let (left, center, right) = (acf[best - 1], acf[best], acf[best + 1]);
+		let denominator = left - 2.0 * center + right;
+		let offset = if denominator < 0.0 { 0.5 * (left - right) / denominator } else { 0.0 };
+
+		let period = (min_lag - 1 + best) as f32 + offset.clamp(-0.5, 0.5);
+
+		self.confidence = (center / energy).clamp(0.0, 1.0);
+
+		if self.period > 0.0 && (period - self.period).abs() < TEMPO_TOLERANCE * self.period {
+			self.period += TEMPO_ALPHA * (period - self.period);
+		} else {
+			self.period = period;
+		}
+
+		self.correct_phase(&envelope);
+	}
+
+	/// Pull the phase towards the offset at which a comb of past beats matches the envelope best.
+	fn correct_phase(&mut self, envelope: &[f32])
+	{
+		let period = self.period;
+		let steps = period.round() as usize;
+
+		let score = |offset: usize| -> f32 {
+			(0 .. PHASE_BEATS).map(|k| offset as f32 + k as f32 * period)
+			                  .filter_map(|back| envelope.len().checked_sub(1 + back.round() as usize))
+			                  .map(|i| envelope[i])
+			                  .sum()
+		};
+
+		let best = (0 .. steps).max_by(|&a, &b| score(a).total_cmp(&score(b)))
+		                       .unwrap_or(0);
+
+		// the last beat was `best` updates ago
+		let measured = best as f32 / period;
+
+		self.phase = (self.phase + PHASE_GAIN * wrap_phase(measured - self.phase)).rem_euclid(1.0);
+	}
+
+	/// Forget the beats of the last update, e.g. if no new spectrum is calculated.
+	pub fn clear(&mut self)
+	{
+		self.beat = false;
+		self.downbeat = false;
+	}
+
+	/// Estimated tempo in BPM, or 0 if it is not known yet.
+	pub fn bpm(&self) -> f32
+	{
+		match self.period {
+			p if p > 0.0 => 60.0 / (p * self.dt),
+			_            => 0.0,
+		}
+	}
+
+	pub fn confidence(&self) -> f32
+	{
+		self.confidence
+	}
+
+	pub fn phase(&self) -> f32
+	{
+		self.phase
+	}
+
+	pub fn is_downbeat(&self) -> bool
+	{
+		self.downbeat
+	}
+}
@@ -0,0 +1,53 @@
+// vim: noet
+
+use std::f32::consts::PI;
+
+use crate::config::WindowFunction;
+
+/// Modified Bessel function of the first kind and order 0, from its power series.
+fn bessel_i0(x: f32) -> f32
+{
+	let mut sum = 1.0;
+	let mut term = 1.0;
+	let mut k = 1.0;
+
+	while term > 1e-8 * sum {
+		term *= (x / (2.0 * k)).powi(2);
+		sum += term;
+		k += 1.0;
+	}
+
+	sum
+}
+
+/// Sum of cosines with the given coefficients and alternating signs.
+fn cosine_sum(coefficients: &[f32], x: f32) -> f32
+{
+	coefficients.iter()
+	            .enumerate()
+	            .map(|(k, a)| if k % 2 == 0 { 1.0 } else { -1.0 } * a * (k as f32 * x).cos())
+	            .sum()
+}
+
+/// Create a periodic window of the given length, as suited for spectral analysis.
+/// `kaiser_beta` is only used by the Kaiser window.
+pub fn create(function: WindowFunction, len: usize, kaiser_beta: f32) -> Vec<f32>
+{
+	let n = len as f32;
+
+	(0..len).map(|i| {
+	          let x = 2.0 * PI * i as f32 / n;
+
+	          match function {
+	              WindowFunction::Hann           => cosine_sum(&[0.5, 0.5], x),
+	              WindowFunction::Hamming        => cosine_sum(&[0.54, 0.46], x),
+	              WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168], x),
+	              WindowFunction::FlatTop        => cosine_sum(&[0.21557895, 0.41663158, 0.27726316, 0.08357895, 0.006947368], x),
+	              WindowFunction::Kaiser         => {
+	                  let r = 2.0 * i as f32 / n - 1.0;
+	                  bessel_i0(kaiser_beta * (1.0 - r * r).sqrt()) / bessel_i0(kaiser_beta)
+	              },
+	          }
+	      })
+	      .collect()
+}